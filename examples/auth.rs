@@ -11,7 +11,7 @@ pub async fn main() -> Result<(), Error> {
         .post_public("login", login)
         .post_public("register", register)
         .post("echo", echo)
-        .build_service()
+        .build_service()?
         .run()
         .await?;
 
@@ -48,6 +48,7 @@ async fn main() {
         .post_public("/register", gateway::register)
         .with_resource::<Event>()
         .build_service()
+        .expect("Invalid routes configured")
         .run()
         .await
         .unwrap();
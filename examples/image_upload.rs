@@ -5,7 +5,7 @@ use tailwag_web_service::{
     application::{
         http::{
             headers::Headers,
-            multipart::MultipartPart,
+            multipart::{MultipartPart, PartContent},
             route::{FromRequest, IntoResponse, PathString, Response},
         },
         WebService,
@@ -22,6 +22,7 @@ pub async fn main() {
         .post("/image/upload", save_image)
         .get("/image/{filename}", load_image)
         .build_service()
+        .expect("Invalid routes configured")
         .run()
         .await
         .unwrap();
@@ -51,7 +52,10 @@ pub async fn main() {
                 return Response::internal_server_error();
             },
         };
-        std::fs::write(filename, image.bytes).unwrap();
+        if let Err(e) = image.file.persist(filename).await {
+            log::error!("Error saving image file: {:?}", e);
+            return Response::internal_server_error();
+        }
         result.into_response()
     }
 }
@@ -76,11 +80,11 @@ impl FromRequest for Image {
                 let url = format!("http://localhost:8081/image/{key}"); // TODO: Unhardcode this. Only for localhost right now. Do a find/replace on hardcoded URLs & localhost specifically
                 let title = parts
                     .remove("title")
-                    .map(|title| String::from_utf8(title.content))
+                    .map(|title| title.text_blocking())
                     .unwrap_or(Ok("".into()))?;
                 let description = parts
                     .remove("description")
-                    .map(|description| String::from_utf8(description.content))
+                    .map(|description| description.text_blocking())
                     .unwrap_or(Ok("".into()))?;
 
                 Image {
@@ -92,7 +96,7 @@ impl FromRequest for Image {
                         title,
                         description,
                     },
-                    bytes: file.content,
+                    file: file.content,
                     mime_type,
                 }
             },
@@ -140,12 +144,11 @@ pub struct ImageMetadata {
 
 fn upload_new_image() {}
 
-#[derive(Clone)]
 pub struct Image {
     metadata: ImageMetadata,
     #[allow(unused)]
     mime_type: ImageMimeType,
-    bytes: Vec<u8>,
+    file: PartContent,
 }
 
 #[derive(Clone)]
@@ -18,6 +18,7 @@ pub async fn main() {
         })
         .post("echo", echo)
         .build_service()
+        .expect("Invalid routes configured")
         .run()
         .await
         .unwrap();
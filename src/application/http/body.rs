@@ -0,0 +1,129 @@
+use std::ops::Deref;
+
+use super::route::{FromRequest, Request};
+
+/// An extractor for the raw body bytes, whatever the media type.
+/// Multipart bodies are the exception, since they are split into parts while they are read.
+pub struct Bytes(pub Vec<u8>);
+
+impl Deref for Bytes {
+    type Target = Vec<u8>;
+    fn deref(&self) -> &Vec<u8> {
+        &self.0
+    }
+}
+
+impl FromRequest for Bytes {
+    fn from(req: Request) -> Result<Self, crate::Error> {
+        req.body.into_bytes().map(Bytes)
+    }
+}
+
+/// An extractor for the body as text, whatever the media type (or without one). Unlike extracting a
+/// `String`, which goes through the body's representation (e.g. a JSON string), the body is taken as-is.
+/// Bodies that aren't valid UTF-8 are rejected with a 400.
+pub struct Text(pub String);
+
+impl Deref for Text {
+    type Target = String;
+    fn deref(&self) -> &String {
+        &self.0
+    }
+}
+
+impl FromRequest for Text {
+    fn from(req: Request) -> Result<Self, crate::Error> {
+        Ok(Text(String::from_utf8(req.body.into_bytes()?)?))
+    }
+}
+
+/// An extractor for the raw body bytes along with their media type, for handlers that accept
+/// several (or arbitrary) media types.
+///
+/// ```ignore
+/// async fn upload(body: RawBody) -> Response {
+///     match body.content_type.as_str() {
+///         "text/csv" => import_csv(&body.bytes),
+///         "application/vnd.ms-excel" => import_xls(&body.bytes),
+///         _ => Response::unsupported_media_type(),
+///     }
+/// }
+/// ```
+pub struct RawBody {
+    /// The media type, lowercased and without parameters. Defaults to `application/octet-stream`
+    /// when the client doesn't send a `Content-Type`. Ref: RFC 9110 8.3
+    pub content_type: String,
+    pub bytes: Vec<u8>,
+}
+
+impl FromRequest for RawBody {
+    fn from(req: Request) -> Result<Self, crate::Error> {
+        let content_type =
+            req.content_type().unwrap_or_else(|| "application/octet-stream".to_string());
+        Ok(RawBody {
+            content_type,
+            bytes: req.body.into_bytes()?,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{Bytes, Text};
+    use crate::application::http::{
+        headers::Headers,
+        route::{FromRequest, HttpBody, HttpMethod, HttpVersion, Request},
+    };
+
+    pub(crate) async fn request(
+        content_type: Option<&str>,
+        body: &[u8],
+    ) -> Request {
+        let mut headers = Headers::empty();
+        if let Some(content_type) = content_type {
+            headers.insert("content-type", content_type);
+        }
+        Request {
+            method: HttpMethod::Post,
+            path: "/".into(),
+            path_params: Default::default(),
+            query: String::new(),
+            query_params: Default::default(),
+            http_version: HttpVersion::V1_1,
+            body: HttpBody::parse(&headers, body.to_vec()).await.unwrap(),
+            headers,
+        }
+    }
+
+    #[tokio::test]
+    async fn takes_untyped_and_vendor_bodies_as_is() {
+        // Without a Content-Type, binary bodies aren't mistaken for (invalid) JSON.
+        let binary = <Bytes as FromRequest>::from(request(None, &[0xff, 0x00]).await).unwrap();
+        assert_eq!(binary.0, vec![0xff, 0x00]);
+
+        let vendor = request(Some("application/vnd.acme+csv"), b"a,b").await;
+        assert_eq!(<Text as FromRequest>::from(vendor).unwrap().0, "a,b");
+        let json = request(Some("application/json"), b"\"quoted\"").await;
+        assert_eq!(<Text as FromRequest>::from(json).unwrap().0, "\"quoted\"");
+        assert!(<Text as FromRequest>::from(request(None, &[0xff]).await).is_err());
+    }
+
+    #[tokio::test]
+    async fn reads_strings_of_any_media_type_and_untyped_json() {
+        let vendor = request(Some("application/vnd.acme+csv"), b"a,b").await;
+        assert_eq!(<String as FromRequest>::from(vendor).unwrap(), "a,b");
+        let binary = request(Some("application/octet-stream"), &[0xff, 0x00]).await;
+        assert!(matches!(
+            <String as FromRequest>::from(binary),
+            Err(crate::Error::UnsupportedMediaType)
+        ));
+
+        #[derive(serde::Deserialize)]
+        struct Login {
+            email_address: String,
+        }
+        let untyped = request(None, br#"{"email_address": "a@b.c"}"#).await;
+        let login = <Login as FromRequest>::from(untyped).unwrap();
+        assert_eq!(login.email_address, "a@b.c");
+    }
+}
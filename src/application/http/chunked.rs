@@ -0,0 +1,190 @@
+use std::io;
+
+use bytes::Bytes;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use tokio_util::io::StreamReader;
+
+use crate::{application::ConfigConstants, errors::BodyError, HttpResult};
+
+use super::headers::Headers;
+
+/// Trailer fields that are never allowed to modify the request, since they control framing, routing,
+/// or authentication. Ref: RFC 9110 6.5.1
+const FORBIDDEN_TRAILERS: [&str; 8] = [
+    "content-length",
+    "transfer-encoding",
+    "content-type",
+    "content-encoding",
+    "host",
+    "authorization",
+    "cookie",
+    "trailer",
+];
+
+/// Reads a `Transfer-Encoding: chunked` body off of the stream. (Ref: RFC 9112 7.1)
+/// Chunk extensions are ignored, and any trailer fields are merged into `headers`.
+///
+/// Fails with `413 Entity Too Large` as soon as the decoded body grows past `ConfigConstants::max_content_length()`.
+pub async fn read_chunked_body<T: AsyncRead + Unpin>(
+    stream: &mut BufReader<T>,
+    headers: &mut Headers,
+) -> HttpResult<Vec<u8>> {
+    let mut decoder = ChunkedDecoder::new(stream, headers, ConfigConstants::max_content_length());
+    let mut body = Vec::new();
+    while let Some(chunk) = decoder.next_chunk().await? {
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Decodes a chunked body one chunk at a time, so that it can be processed as it arrives instead of
+/// being buffered first (see [ChunkedDecoder::into_reader]).
+pub struct ChunkedDecoder<'a, T> {
+    stream: &'a mut BufReader<T>,
+    headers: &'a mut Headers,
+    read: u64,
+    max_length: u64,
+}
+
+impl<'a, T: AsyncRead + Unpin> ChunkedDecoder<'a, T> {
+    pub fn new(
+        stream: &'a mut BufReader<T>,
+        headers: &'a mut Headers,
+        max_length: u64,
+    ) -> Self {
+        Self {
+            stream,
+            headers,
+            read: 0,
+            max_length,
+        }
+    }
+
+    /// The data of the next chunk, or `None` once the last chunk and the trailer section have been read.
+    pub async fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut line = String::new();
+        (&mut *self.stream)
+            .take(ConfigConstants::request_line_max_length())
+            .read_line(&mut line)
+            .await?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = u64::from_str_radix(size, 16)
+            .map_err(|_| BodyError::Invalid(format!("Invalid chunk size: {}", line.trim())))?;
+        if size == 0 {
+            self.read_trailers().await?;
+            return Ok(None);
+        }
+        if size > self.max_length - self.read {
+            Err(BodyError::TooLarge)?;
+        }
+        self.read += size;
+
+        let mut chunk = vec![0; size as usize];
+        self.stream.read_exact(&mut chunk).await?;
+        let mut crlf = [0; 2];
+        self.stream.read_exact(&mut crlf).await?;
+        if crlf != *b"\r\n" {
+            Err(BodyError::Invalid("Chunk data was not terminated with CRLF.".into()))?;
+        }
+        Ok(Some(chunk))
+    }
+
+    /// Trailer section: header fields, terminated by an empty line.
+    async fn read_trailers(&mut self) -> io::Result<()> {
+        let mut line = String::new();
+        let mut trailers = (&mut *self.stream).take(ConfigConstants::headers_max_length());
+        loop {
+            line.clear();
+            if trailers.read_line(&mut line).await? <= 2 {
+                return Ok(());
+            }
+            let name = line.split_once(':').map(|(name, _)| name.trim().to_lowercase());
+            match name {
+                Some(name) if FORBIDDEN_TRAILERS.contains(&name.as_str()) => {
+                    log::warn!("Ignoring disallowed trailer field: {}", name);
+                },
+                _ => {
+                    self.headers.insert_parsed(&line).map_err(|_| {
+                        BodyError::Invalid(format!("Invalid trailer field: {}", line.trim()))
+                    })?;
+                },
+            }
+        }
+    }
+
+    /// The decoded body as an `AsyncRead`, e.g. to parse a multipart body as it arrives.
+    /// The trailer fields are merged into the headers once the body has been read to the end.
+    pub fn into_reader(self) -> impl AsyncRead + Unpin + 'a {
+        let chunks = futures::stream::try_unfold(self, |mut decoder| async move {
+            let chunk = decoder.next_chunk().await?;
+            Ok::<_, io::Error>(chunk.map(|chunk| (Bytes::from(chunk), decoder)))
+        });
+        StreamReader::new(Box::pin(chunks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::BufReader;
+
+    use crate::application::http::{
+        headers::Headers,
+        multipart::{read_streamed_multipart, MultipartLimits},
+        route::HttpBody,
+    };
+
+    use super::{read_chunked_body, ChunkedDecoder};
+
+    #[tokio::test]
+    async fn decodes_chunks_and_trailers() {
+        let raw = b"4;ext=1\r\nWiki\r\n6\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\nExpires: never\r\nHost: evil\r\n\r\nGET /next";
+        let mut stream = BufReader::new(&raw[..]);
+        let mut headers = Headers::from(vec![("host", "localhost")]);
+
+        let body = read_chunked_body(&mut stream, &mut headers).await.unwrap();
+
+        assert_eq!(body, b"Wikipedia in \r\n\r\nchunks.");
+        assert_eq!(headers.get("expires").map(|h| h.to_string()), Some("never".to_string()));
+        assert_eq!(headers.get("host").map(|h| h.to_string()), Some("localhost".to_string()));
+        // The next pipelined request must be left untouched.
+        let mut rest = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut rest).await.unwrap();
+        assert_eq!(rest, "GET /next");
+    }
+
+    #[tokio::test]
+    async fn streams_chunked_multipart_bodies() {
+        let raw = b"1C\r\n--XyZ\r\nContent-Disposition: \r\n\
+            29\r\nform-data; name=\"tag\"\r\n\r\nfirst\r\n--XyZ--\r\n\r\n\
+            0\r\nExpires: never\r\n\r\nGET /next";
+        let mut stream = BufReader::new(&raw[..]);
+        let mut headers = Headers::default();
+        let limits = MultipartLimits {
+            max_length: 1024,
+            max_part_length: 64,
+            spill_threshold: 64,
+            max_memory: 1024,
+        };
+
+        let body = ChunkedDecoder::new(&mut stream, &mut headers, limits.max_length).into_reader();
+        let body = read_streamed_multipart(body, "boundary=XyZ", &limits).await.unwrap();
+
+        let HttpBody::Multipart(mut parts) = body else {
+            panic!("Expected a multipart body, got {:?}", body);
+        };
+        assert_eq!(parts.remove("tag").unwrap().text().await.unwrap(), "first");
+        assert_eq!(headers.get("expires").map(|h| h.to_string()), Some("never".to_string()));
+        let mut rest = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut rest).await.unwrap();
+        assert_eq!(rest, "GET /next");
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_chunk_size() {
+        let raw = b"zz\r\nabc\r\n0\r\n\r\n";
+        let mut stream = BufReader::new(&raw[..]);
+        let mut headers = Headers::default();
+
+        assert!(read_chunked_body(&mut stream, &mut headers).await.is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use cookie::CookieJar;
+pub use cookie::{time::Duration, Cookie, CookieBuilder, Key, SameSite};
+
+use super::{
+    headers::Headers,
+    route::{FromRequestContext, RequestContext},
+};
+
+/// Secrets shorter than this can't be expanded into a key safely.
+const MIN_SECRET_LENGTH: usize = 32;
+
+/// The raw `Cookie` header(s) of the current request, stored in the request context so that extractors
+/// can get to them after the request itself has been consumed.
+pub(crate) struct CookieHeader(pub(crate) Vec<String>);
+
+/// The key used to sign and encrypt cookies, shared by the whole server.
+#[derive(Clone)]
+pub struct CookieKey(Key);
+
+impl CookieKey {
+    pub fn new(key: Key) -> Self {
+        Self(key)
+    }
+
+    /// Derives the key from the `COOKIE_SECRET` environment variable, which must be at least 32 bytes.
+    /// Without one, a random key is generated, so signed and private cookies won't survive a restart.
+    pub fn from_env() -> Self {
+        match std::env::var("COOKIE_SECRET") {
+            Ok(secret) if secret.len() >= MIN_SECRET_LENGTH => {
+                Self(Key::derive_from(secret.as_bytes()))
+            },
+            Ok(_) => {
+                log::warn!(
+                    "COOKIE_SECRET must be at least {} bytes long - using a random key instead.",
+                    MIN_SECRET_LENGTH
+                );
+                Self(Key::generate())
+            },
+            Err(_) => {
+                log::warn!("COOKIE_SECRET is not set - signed and private cookies won't survive a restart.");
+                Self(Key::generate())
+            },
+        }
+    }
+
+    /// Signs the cookie's value, so that it can be read but not changed by the client.
+    pub fn sign(
+        &self,
+        cookie: impl Into<Cookie<'static>>,
+    ) -> Cookie<'static> {
+        let cookie = cookie.into();
+        let name = cookie.name().to_string();
+        let mut jar = CookieJar::new();
+        jar.signed_mut(&self.0).add(cookie);
+        jar.get(&name).cloned().expect("The cookie was just added to the jar.")
+    }
+
+    /// Encrypts the cookie's value, so that it can be neither read nor changed by the client.
+    pub fn encrypt(
+        &self,
+        cookie: impl Into<Cookie<'static>>,
+    ) -> Cookie<'static> {
+        let cookie = cookie.into();
+        let name = cookie.name().to_string();
+        let mut jar = CookieJar::new();
+        jar.private_mut(&self.0).add(cookie);
+        jar.get(&name).cloned().expect("The cookie was just added to the jar.")
+    }
+}
+
+/// Starts a cookie with safe defaults: `HttpOnly`, `Secure`, `SameSite=Lax`, and `Path=/`.
+/// Any of them can be overridden, along with `Domain`, `Max-Age`, `Expires`, etc.
+///
+/// ```ignore
+/// let cookie = cookies::build("theme", "dark").http_only(false).max_age(Duration::days(365));
+/// Response::ok().with_cookie(cookie)
+/// ```
+pub fn build(
+    name: impl Into<String>,
+    value: impl Into<String>,
+) -> CookieBuilder<'static> {
+    Cookie::build((name.into(), value.into()))
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Lax)
+        .path("/")
+}
+
+/// An extractor for the cookies sent with the request. Ref: RFC 6265 5.4
+///
+/// Signed and private cookies are verified (or decrypted) with the server's [CookieKey]. If they've
+/// been tampered with, they're treated as missing.
+pub struct Cookies {
+    jar: CookieJar,
+    key: CookieKey,
+}
+
+impl Cookies {
+    /// Parses the cookies out of the request headers. Malformed cookies are skipped.
+    pub fn from_headers(
+        headers: &Headers,
+        key: CookieKey,
+    ) -> Self {
+        Self::parse(headers.get_all("cookie").map(|header| header.as_str()), key)
+    }
+
+    fn parse<'a>(
+        headers: impl Iterator<Item = &'a str>,
+        key: CookieKey,
+    ) -> Self {
+        let mut jar = CookieJar::new();
+        for header in headers {
+            for cookie in Cookie::split_parse_encoded(header.to_string()).flatten() {
+                jar.add_original(cookie.into_owned());
+            }
+        }
+        Self {
+            jar,
+            key,
+        }
+    }
+
+    pub fn get(
+        &self,
+        name: &str,
+    ) -> Option<&Cookie<'static>> {
+        self.jar.get(name)
+    }
+
+    pub fn value(
+        &self,
+        name: &str,
+    ) -> Option<&str> {
+        self.get(name).map(|cookie| cookie.value())
+    }
+
+    /// A cookie set with [CookieKey::sign], if its signature is valid.
+    pub fn signed(
+        &self,
+        name: &str,
+    ) -> Option<Cookie<'static>> {
+        self.jar.signed(&self.key.0).get(name)
+    }
+
+    /// A cookie set with [CookieKey::encrypt], decrypted.
+    pub fn private(
+        &self,
+        name: &str,
+    ) -> Option<Cookie<'static>> {
+        self.jar.private(&self.key.0).get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cookie<'static>> {
+        self.jar.iter()
+    }
+
+    /// The server's key, for signing or encrypting cookies sent back in the response.
+    pub fn key(&self) -> &CookieKey {
+        &self.key
+    }
+}
+
+impl FromRequestContext for Cookies {
+    fn from_request_context(ctx: &RequestContext) -> Result<Self, crate::Error> {
+        let key = ctx.server_data.get::<CookieKey>().cloned().ok_or_else(|| {
+            crate::Error::InternalServerError("No cookie key is configured.".into())
+        })?;
+        let headers = ctx.get_request_data::<CookieHeader>().map_or(&[][..], |h| h.0.as_slice());
+        Ok(Self::parse(headers.iter().map(String::as_str), key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build, CookieKey, Cookies, Key};
+    use crate::application::http::headers::Headers;
+
+    #[test]
+    fn reads_plain_signed_and_private_cookies() {
+        let key = CookieKey::new(Key::generate());
+        let signed = key.sign(build("user", "42"));
+        let private = key.encrypt(build("token", "secret"));
+        let header = format!(
+            "theme=dark; {}={}; {}={}",
+            signed.name(),
+            signed.value(),
+            private.name(),
+            private.value()
+        );
+        let headers = Headers::from(vec![("cookie", header.as_str()), ("cookie", "lang=en")]);
+
+        let cookies = Cookies::from_headers(&headers, key.clone());
+        assert_eq!(cookies.value("theme"), Some("dark"));
+        assert_eq!(cookies.value("lang"), Some("en"));
+        assert_eq!(cookies.signed("user").map(|c| c.value().to_string()), Some("42".into()));
+        assert_eq!(cookies.private("token").map(|c| c.value().to_string()), Some("secret".into()));
+        assert_ne!(private.value(), "secret");
+
+        let tampered = Headers::from(vec![("cookie", "user=43")]);
+        assert!(Cookies::from_headers(&tampered, key).signed("user").is_none());
+    }
+}
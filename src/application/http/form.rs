@@ -0,0 +1,46 @@
+use std::ops::Deref;
+
+use serde::de::DeserializeOwned;
+
+use super::route::{FromRequest, HttpBody, Request};
+use crate::errors::deserialize_tracked;
+
+/// Deserializes a url-encoded string (a form body or a query string), keeping track of which field failed.
+pub(crate) fn deserialize_urlencoded<T: DeserializeOwned>(
+    input: &str,
+    context: &str,
+) -> Result<T, crate::Error> {
+    let deserializer =
+        serde_urlencoded::Deserializer::new(form_urlencoded::parse(input.as_bytes()));
+    deserialize_tracked(deserializer, context)
+}
+
+/// An extractor that deserializes an `application/x-www-form-urlencoded` body into `T`, e.g. from a
+/// plain HTML `<form>`. Any other content type is rejected with a 415.
+///
+/// ```ignore
+/// #[derive(Deserialize)]
+/// struct Signup {
+///     email_address: String,
+///     newsletter: Option<bool>,
+/// }
+///
+/// async fn signup(Form(signup): Form<Signup>) -> Response { ... }
+/// ```
+pub struct Form<T>(pub T);
+
+impl<T> Deref for Form<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for Form<T> {
+    fn from(req: Request) -> Result<Self, crate::Error> {
+        match &req.body {
+            HttpBody::Form(body) => deserialize_urlencoded(body, "Invalid form body").map(Form),
+            _ => crate::Error::unsupported_media_type(),
+        }
+    }
+}
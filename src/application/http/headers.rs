@@ -1,11 +1,7 @@
-use std::{
-    collections::HashMap,
-    fmt::Display,
-    io::{BufRead, BufReader},
-    ops::DerefMut,
-};
+use std::{collections::HashMap, fmt::Display, str::FromStr};
 
 use tailwag_macros::Deref;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
 
 use crate::{application::ConfigConstants, Error};
 
@@ -67,14 +63,12 @@ impl HeaderValue {
     }
 }
 
-#[derive(Debug, Deref)]
+/// The header fields of a request or response. Names are case-insensitive, a name can have several
+/// values (e.g. `Set-Cookie`), and fields are kept in the order they were added, which is the order
+/// they're sent in. Ref: RFC 9110 5.2, 5.3
+#[derive(Debug, Clone)]
 pub struct Headers {
-    headers: HashMap<HeaderName, HeaderValue>,
-}
-impl DerefMut for Headers {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.headers
-    }
+    headers: Vec<(HeaderName, HeaderValue)>,
 }
 
 impl Headers {
@@ -87,16 +81,18 @@ impl Headers {
         }))
     }
 
-    pub fn parse_headers<T: std::io::Read>(stream: &mut BufReader<T>) -> Result<Self, Error> {
+    pub async fn parse_headers<T: AsyncRead + Unpin>(
+        stream: &mut BufReader<T>
+    ) -> Result<Self, Error> {
         let mut headers = Headers::default();
         let mut line = String::new();
 
-        let mut stream = std::io::Read::take(stream, ConfigConstants::headers_max_length());
-        while stream.read_line(&mut line)? > 2 {
+        let mut stream = stream.take(ConfigConstants::headers_max_length());
+        while stream.read_line(&mut line).await? > 2 {
             headers.insert_parsed(&line)?;
             line = String::new();
         }
-        Ok(dbg!(headers))
+        Ok(headers)
     }
 }
 
@@ -104,48 +100,245 @@ impl Default for Headers {
     fn default() -> Self {
         // Creates a sensible-defaults header set based on OWASP recommendations.
         // Ref: https://cheatsheetseries.owasp.org/cheatsheets/HTTP_Headers_Cheat_Sheet.html
-        Self {
-            headers: vec![
-                ("X-Frame-Options", "DENY"),
-                ("X-Content-Type-Options", "nosniff"),
-                ("Referrer-Policy", "strict-origin-when-cross-origin"),
-            ]
-            .into_iter()
-            .map(|(k, v)| (k.into(), v.into()))
-            .collect(),
-        }
+        Headers::from(vec![
+            ("X-Frame-Options", "DENY"),
+            ("X-Content-Type-Options", "nosniff"),
+            ("Referrer-Policy", "strict-origin-when-cross-origin"),
+        ])
     }
 }
 
 impl Headers {
+    /// Headers without the default response headers.
+    pub fn empty() -> Self {
+        Self {
+            headers: Vec::new(),
+        }
+    }
+
+    /// Parses a header line, and appends it to any earlier values for the same name.
     pub fn insert_parsed(
         &mut self,
         header_line: &str,
     ) -> Result<(HeaderName, &HeaderValue), Error> {
-        let Some((name, value)) = dbg!(header_line).split_once(':') else {
+        let Some((name, value)) = header_line.split_once(':') else {
             return Err(Error::BadRequest(format!("Failed to parse header: {}", header_line)));
         };
+        // Proxies may read `Name :` differently, which is a request smuggling vector. Ref: RFC 9112 5.1
+        if name.ends_with(char::is_whitespace) {
+            return Err(Error::BadRequest(format!(
+                "Whitespace between the header name and colon: {}",
+                header_line.trim_end()
+            )));
+        }
 
-        let name = name.to_lowercase();
-        self.headers.insert(name.clone(), value.trim().into());
-        let value = self
+        let name = name.trim().to_lowercase();
+        self.headers.push((name.clone(), value.trim().into()));
+        let (_, value) = self
             .headers
-            .get(&name)
-            .expect("We literally just added this to the map on the previous line.");
+            .last()
+            .expect("We literally just added this to the list on the previous line.");
         Ok((name, value))
     }
+
+    /// The first value for the header.
     pub fn get(
         &self,
         header_name: &str,
     ) -> Option<&HeaderValue> {
-        self.headers.get(&header_name.to_lowercase())
+        self.get_all(header_name).next()
+    }
+
+    /// Every value for the header, in the order they were added.
+    pub fn get_all<'a>(
+        &'a self,
+        header_name: &'a str,
+    ) -> impl Iterator<Item = &'a HeaderValue> + 'a {
+        self.headers
+            .iter()
+            .filter(move |(name, _)| name.eq_ignore_ascii_case(header_name))
+            .map(|(_, value)| value)
+    }
+
+    /// Parses the first value for the header, e.g. `headers.get_as::<u64>("max-forwards")`.
+    pub fn get_as<T: FromStr>(
+        &self,
+        header_name: &str,
+    ) -> Option<T> {
+        self.get(header_name).and_then(|value| value.trim().parse().ok())
+    }
+
+    pub fn contains_key(
+        &self,
+        header_name: &str,
+    ) -> bool {
+        self.get(header_name).is_some()
+    }
+
+    /// Sets the header, replacing any values it already has. The header keeps its place if it was
+    /// already set, and is added at the end otherwise.
+    pub fn insert(
+        &mut self,
+        header_name: impl Into<HeaderName>,
+        value: impl Into<HeaderValue>,
+    ) {
+        let name = header_name.into().to_lowercase();
+        match self.headers.iter().position(|(existing, _)| existing == &name) {
+            Some(index) => {
+                let rest = self.headers.split_off(index + 1);
+                self.headers[index].1 = value.into();
+                self.headers.extend(rest.into_iter().filter(|(existing, _)| existing != &name));
+            },
+            None => self.headers.push((name, value.into())),
+        }
+    }
+
+    /// Adds another value for the header, after any it already has.
+    pub fn append(
+        &mut self,
+        header_name: impl Into<HeaderName>,
+        value: impl Into<HeaderValue>,
+    ) {
+        self.headers.push((header_name.into().to_lowercase(), value.into()));
+    }
+
+    /// Removes every value for the header, returning the first.
+    pub fn remove(
+        &mut self,
+        header_name: &str,
+    ) -> Option<HeaderValue> {
+        let removed = self
+            .headers
+            .iter()
+            .position(|(name, _)| name.eq_ignore_ascii_case(header_name))
+            .map(|index| self.headers.remove(index).1);
+        self.headers.retain(|(name, _)| !name.eq_ignore_ascii_case(header_name));
+        removed
+    }
+
+    /// Every header field, in order. Headers with several values appear once per value.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &HeaderValue)> {
+        self.headers.iter().map(|(name, value)| (name.as_str(), value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    /// The media type from `Content-Type`, lowercased and without its parameters.
+    pub fn content_type(&self) -> Option<String> {
+        self.get("content-type")
+            .map(|ct| ct.split(';').next().unwrap_or_default().trim().to_lowercase())
+    }
+
+    /// The length of the body, from `Content-Length`. Repeated values (or a list, e.g. `5, 5`) are only
+    /// accepted if they all agree, since otherwise there's no telling where the body ends. Ref: RFC 9112 6.3
+    pub fn content_length(&self) -> Result<Option<u64>, Error> {
+        let mut content_length = None;
+        for value in self.get_all("content-length").flat_map(|value| value.split(',')) {
+            let value = value.trim();
+            // Only plain digits, so e.g. `+5` isn't read as 5.
+            let length = Some(value)
+                .filter(|value| value.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|value| value.parse::<u64>().ok());
+            let Some(length) = length else {
+                Err(Error::BadRequest(format!("Invalid Content-Length: {}", value)))?
+            };
+            if content_length.is_some_and(|content_length| content_length != length) {
+                Err(Error::BadRequest("Conflicting Content-Length values.".into()))?;
+            }
+            content_length = Some(length);
+        }
+        Ok(content_length)
+    }
+
+    pub fn host(&self) -> Option<&str> {
+        self.get("host").map(|host| host.as_str())
+    }
+
+    /// Whether the `Transfer-Encoding` header ends with `chunked`. Ref: RFC 9112 6.1
+    pub fn is_chunked(&self) -> bool {
+        self.get_all("transfer-encoding")
+            .flat_map(|te| te.split(','))
+            .last()
+            .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+    }
+}
+
+impl<'a> IntoIterator for &'a Headers {
+    type Item = (&'a str, &'a HeaderValue);
+    type IntoIter = Box<dyn Iterator<Item = Self::Item> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
     }
 }
 
 impl From<Vec<(&str, &str)>> for Headers {
     fn from(value: Vec<(&str, &str)>) -> Self {
-        Headers {
-            headers: value.into_iter().map(|(name, val)| (name.into(), val.into())).collect(),
+        let mut headers = Headers::empty();
+        for (name, val) in value {
+            headers.append(name, val);
         }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Headers;
+
+    #[test]
+    fn keeps_every_value_in_order() {
+        let mut headers = Headers::empty();
+        headers.insert("Content-Type", "text/plain");
+        headers.append("Set-Cookie", "a=1");
+        headers.append("set-cookie", "b=2");
+        headers.insert_parsed("Content-Length: 12\r\n").unwrap();
+        assert_eq!(headers.get("SET-COOKIE").map(|v| v.as_str()), Some("a=1"));
+        assert_eq!(
+            headers.get_all("set-cookie").map(|v| v.as_str()).collect::<Vec<_>>(),
+            ["a=1", "b=2"]
+        );
+        assert_eq!(headers.content_length().unwrap(), Some(12));
+
+        headers.insert("Content-Type", "application/json");
+        let names: Vec<_> = headers.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["content-type", "set-cookie", "set-cookie", "content-length"]);
+        assert_eq!(headers.content_type().as_deref(), Some("application/json"));
+
+        assert_eq!(headers.remove("Set-Cookie").map(|v| v.to_string()), Some("a=1".to_string()));
+        assert!(!headers.contains_key("set-cookie"));
+        assert_eq!(headers.len(), 2);
+    }
+
+    #[test]
+    fn rejects_conflicting_content_lengths() {
+        let lengths = |values: &[&str]| {
+            let headers = Headers::from(
+                values.iter().map(|value| ("content-length", *value)).collect::<Vec<_>>(),
+            );
+            headers.content_length()
+        };
+        assert_eq!(lengths(&[]).unwrap(), None);
+        assert_eq!(lengths(&["5", "5"]).unwrap(), Some(5));
+        assert_eq!(lengths(&["5, 5"]).unwrap(), Some(5));
+        assert!(lengths(&["5", "6"]).is_err());
+        assert!(lengths(&["5, 6"]).is_err());
+        assert!(lengths(&["+5"]).is_err());
+        assert!(lengths(&[""]).is_err());
+    }
+
+    #[test]
+    fn rejects_whitespace_before_the_colon() {
+        let mut headers = Headers::empty();
+        assert!(headers.insert_parsed("Transfer-Encoding : chunked\r\n").is_err());
+        assert!(headers.insert_parsed("Transfer-Encoding\t: chunked\r\n").is_err());
+        assert!(headers.insert_parsed("Transfer-Encoding:  chunked\r\n").is_ok());
+        assert!(headers.is_chunked());
     }
 }
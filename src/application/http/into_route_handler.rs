@@ -5,9 +5,21 @@ use std::pin::Pin;
 
 use std::future::Future;
 
-use crate::application::http::route::{FromRequest, IntoResponse, RouteHandler};
-
-use super::route::{RequestContext, Response};
+use crate::application::http::route::{
+    FromRequest, FromRequestContext, IntoResponse, RouteHandler,
+};
+
+use super::route::RequestContext;
+
+/// Extracts the handler's request argument, or returns the extractor's rejection as the response.
+macro_rules! extract_from_request {
+    ($request_id:ident, $req:ident) => {
+        match $request_id::from($req) {
+            Ok(extracted) => extracted,
+            Err(err) => return <$request_id as FromRequest>::rejection(err),
+        }
+    };
+}
 
 impl IntoRouteHandler<(), (), ()> for RouteHandler {
     fn into(self) -> RouteHandler {
@@ -39,9 +51,7 @@ where
         RouteHandler {
             handler: Box::new(move |req, _ctx| {
                 Box::pin(async move {
-                    let Ok(request) = I::from(req) else {
-                        return Response::bad_request();
-                    };
+                    let request = extract_from_request!(I, req);
                     self(request).await.into_response()
                 })
             }),
@@ -106,9 +116,7 @@ where
         RouteHandler {
             handler: Box::new(move |req, _ctx| {
                 Box::pin(async move {
-                    let Ok(req) = I::from(req) else {
-                        return Response::bad_request();
-                    };
+                    let req = extract_from_request!(I, req);
                     self(req).await.into_response()
                 })
             }),
@@ -127,9 +135,7 @@ where
         RouteHandler {
             handler: Box::new(move |req, _ctx| {
                 Box::pin(async move {
-                    let Ok(req) = I::from(req) else {
-                        return Response::bad_request();
-                    };
+                    let req = extract_from_request!(I, req);
                     self(req).into_response()
                 })
             }),
@@ -137,6 +143,16 @@ where
     }
 }
 
+/// Extracts a handler argument from the request context, or returns the extractor's rejection as the response.
+macro_rules! extract_from_context {
+    ($context_id:ident, $ctx:ident) => {
+        match $context_id::from_request_context(&$ctx) {
+            Ok(extracted) => extracted,
+            Err(err) => return <$context_id as FromRequestContext>::rejection(err),
+        }
+    };
+}
+
 macro_rules! generate_trait_impl {
     (R1, $($context_id:ident),*) => {
         // async fn(FromRequest, FromContext1, ..., FromContextN, RequestContext) -> IntoResponse;
@@ -145,7 +161,7 @@ macro_rules! generate_trait_impl {
         where
             F: Send + Sync + Fn(I, $($context_id,)* RequestContext) -> Fut + Send + Copy + 'static + ,
             I: FromRequest + Sized + 'static,
-            $($context_id: for<'a> From<&'a RequestContext> + Sized + 'static,)*
+            $($context_id: FromRequestContext + Sized + 'static,)*
             O: IntoResponse + Sized + Send + 'static,
             Fut: Future<Output = O> + 'static +  Send,
         {
@@ -153,12 +169,10 @@ macro_rules! generate_trait_impl {
                 RouteHandler {
                     handler: Box::new(move |req, ctx| {
                         Box::pin(async move {
-                            let Ok(req) = I::from(req) else {
-                                return Response::bad_request();
-                            };
+                            let req = extract_from_request!(I, req);
 
                             self(
-                                req, $($context_id::from(&ctx),)* ctx)
+                                req, $(extract_from_context!($context_id, ctx),)* ctx)
                                 .await
                                 .into_response()
                         })
@@ -173,7 +187,7 @@ macro_rules! generate_trait_impl {
         where
             F: Send + Sync + Fn(I, $($context_id),*, RequestContext) -> O + Send + Copy + 'static + ,
             I: FromRequest + Sized + 'static,
-            $($context_id: for<'a> From<&'a RequestContext> + Sized + 'static,)*
+            $($context_id: FromRequestContext + Sized + 'static,)*
             O: IntoResponse + Sized + Send + 'static,
         {
             fn into(self) -> RouteHandler {
@@ -181,10 +195,8 @@ macro_rules! generate_trait_impl {
                     handler: Box::new(move |req, ctx| {
                         Box::pin(async move {
 
-                            let Ok(req) = I::from(req) else {
-                                return Response::bad_request();
-                            };
-                            self(req, $($context_id::from(&ctx),)* ctx)
+                            let req = extract_from_request!(I, req);
+                            self(req, $(extract_from_context!($context_id, ctx),)* ctx)
                                 .into_response()
                         })
                     }),
@@ -198,7 +210,7 @@ macro_rules! generate_trait_impl {
         where
             F: Send + Sync + Fn(I, $($context_id),*) -> Fut + Send + Copy + 'static + ,
             I: FromRequest + Sized + 'static,
-            $($context_id: for<'a> From<&'a RequestContext> + Sized + 'static,)*
+            $($context_id: FromRequestContext + Sized + 'static,)*
             O: IntoResponse + Sized + Send + 'static,
             Fut: Future<Output = O> + 'static +   Send,
         {
@@ -206,12 +218,10 @@ macro_rules! generate_trait_impl {
                 RouteHandler {
                     handler: Box::new(move |req, ctx| {
                         Box::pin(async move {
-                            let Ok(req) = I::from(req) else {
-                                return Response::bad_request();
-                            };
+                            let req = extract_from_request!(I, req);
 
                             self(
-                                req, $($context_id::from(&ctx)),*)
+                                req, $(extract_from_context!($context_id, ctx)),*)
                                 .await
                                 .into_response()
                         })
@@ -226,7 +236,7 @@ macro_rules! generate_trait_impl {
         where
             F: Send + Sync + Fn(I, $($context_id),*) -> O + Send + Copy + 'static + ,
             I: FromRequest + Sized + 'static,
-            $($context_id: for<'a> From<&'a RequestContext> + Sized + 'static,)*
+            $($context_id: FromRequestContext + Sized + 'static,)*
             O: IntoResponse + Sized + Send + 'static,
         {
             fn into(self) -> RouteHandler {
@@ -234,10 +244,8 @@ macro_rules! generate_trait_impl {
                     handler: Box::new(move |req, ctx| {
                         Box::pin(async move {
 
-                            let Ok(req) = I::from(req) else {
-                                return Response::bad_request();
-                            };
-                            self(req, $($context_id::from(&ctx)),*)
+                            let req = extract_from_request!(I, req);
+                            self(req, $(extract_from_context!($context_id, ctx)),*)
                                 .into_response()
                         })
                     }),
@@ -252,7 +260,7 @@ macro_rules! generate_trait_impl {
             IntoRouteHandler<F, (Fut, $($context_id,)*), ($($context_id,)* (O, (), Fut))> for F
         where
             F: Send + Sync + Fn($($context_id),*) -> Fut + Send + Copy + 'static + ,
-            $($context_id: for<'a> From<&'a RequestContext> + Sized + 'static,)*
+            $($context_id: FromRequestContext + Sized + 'static,)*
             O: IntoResponse + Sized + Send + 'static,
             Fut: Future<Output = Result<O, crate::Error>> +   'static + Send,
         {
@@ -262,7 +270,7 @@ macro_rules! generate_trait_impl {
                         Box::pin(async move {
                             match
                                 self(
-                                $($context_id::from(&ctx)),*)
+                                $(extract_from_context!($context_id, ctx)),*)
                                 .await {
                                     Ok(response) => response.into_response(),
                                     Err(err) => err.into_response(),
@@ -282,7 +290,7 @@ macro_rules! generate_trait_impl {
         where
             F: Send + Sync + Fn(I, $($context_id),*) -> Fut + Send + Copy + 'static + ,
             I: FromRequest + Sized + 'static,
-            $($context_id: for<'a> From<&'a RequestContext> + Sized + 'static,)*
+            $($context_id: FromRequestContext + Sized + 'static,)*
             O: IntoResponse + Sized + Send + 'static,
             Fut: Future<Output = Result<O, crate::Error>> + 'static +  Send,
         {
@@ -290,13 +298,11 @@ macro_rules! generate_trait_impl {
                 RouteHandler {
                     handler: Box::new(move |req, ctx| {
                         Box::pin(async move {
-                            let Ok(req) = I::from(req) else {
-                                return Response::bad_request();
-                            };
+                            let req = extract_from_request!(I, req);
 
                             match
                                 self(
-                                req, $($context_id::from(&ctx)),*)
+                                req, $(extract_from_context!($context_id, ctx)),*)
                                 .await {
                                     Ok(response) => response.into_response(),
                                     Err(err) => err.into_response(),
@@ -320,14 +326,14 @@ pub struct Nothing3Async;
 impl<F, C, O, Fut> IntoRouteHandler<F, Nothing3Async, (C, O, Fut)> for F
 where
     F: Send + Sync + Fn(C) -> Fut + Send + Copy + 'static,
-    C: for<'a> From<&'a RequestContext> + Sized + 'static,
+    C: FromRequestContext + Sized + 'static,
     O: IntoResponse + Sized + Send + 'static,
     Fut: Future<Output = O> + 'static + Send,
 {
     fn into(self) -> RouteHandler {
         RouteHandler {
             handler: Box::new(move |_req, ctx| {
-                Box::pin(async move { self(C::from(&ctx)).await.into_response() })
+                Box::pin(async move { self(extract_from_context!(C, ctx)).await.into_response() })
             }),
         }
     }
@@ -336,13 +342,13 @@ pub struct Nothing3;
 impl<F, C, O, Fut> IntoRouteHandler<F, Nothing3, (C, O, Fut)> for F
 where
     F: Send + Sync + Fn(C) -> O + Send + Copy + 'static,
-    C: for<'a> From<&'a RequestContext> + Sized + 'static,
+    C: FromRequestContext + Sized + 'static,
     O: IntoResponse + Sized + Send + 'static,
 {
     fn into(self) -> RouteHandler {
         RouteHandler {
             handler: Box::new(move |_req, ctx| {
-                Box::pin(async move { self(C::from(&ctx)).into_response() })
+                Box::pin(async move { self(extract_from_context!(C, ctx)).into_response() })
             }),
         }
     }
@@ -1,7 +1,19 @@
+pub mod body;
+pub mod chunked;
+pub mod cookies;
+pub mod form;
 pub mod headers;
 pub mod into_route_handler;
 pub mod multipart;
+pub mod negotiation;
+pub mod path_params;
+pub mod query;
+pub mod rejection;
+pub mod request_id;
+pub mod response_body;
 pub mod route;
+pub mod route_error;
+pub mod serializers;
 
 use serde::Serialize;
 
@@ -1,129 +1,450 @@
-use std::{
-    collections::HashMap,
-    io::{BufRead, BufReader},
+use std::{io, path::Path};
+
+use tempfile::NamedTempFile;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    runtime::RuntimeFlavor,
 };
 
-use crate::HttpResult;
+use crate::{
+    application::{middleware::compression::is_content_encoded, ConfigConstants},
+    HttpResult,
+};
 
 use super::{
     headers::Headers,
     route::{HttpBody, Request},
 };
 
+/// How much is read off of the stream at a time.
+const READ_SIZE: usize = 16 * 1024;
+
 #[derive(Debug, Default)]
 pub struct MultipartPart {
     pub headers: Headers,
     pub name: String,
-    pub content: Vec<u8>,
+    pub content: PartContent,
 }
 
-enum BoundaryMatch {
-    None,
-    Boundary,
-    EndBoundary,
+impl MultipartPart {
+    /// The filename the client sent for this part, if it is a file.
+    pub fn filename(&self) -> Option<String> {
+        let (_, params) = self.headers.get("content-disposition")?.split_once(';')?;
+        Headers::parse_params(params).get("filename").map(|s| s.trim_matches('"').to_owned())
+    }
+
+    /// The content as (UTF-8) text, e.g. for regular form fields.
+    pub async fn text(self) -> HttpResult<String> {
+        Ok(String::from_utf8(self.content.into_bytes().await?)?)
+    }
+
+    /// Like [MultipartPart::text], for synchronous code such as a [FromRequest](super::route::FromRequest) impl.
+    pub fn text_blocking(self) -> HttpResult<String> {
+        Ok(String::from_utf8(self.content.into_bytes_blocking()?)?)
+    }
 }
 
-#[derive(Default)]
-enum MultipartParserState {
-    #[default]
-    Start,
-    Headers,
-    Body,
-    Done,
+/// The content of a single part. Small parts are kept in memory, while larger ones (usually files)
+/// are spooled to a temp file as they are read.
+#[derive(Debug)]
+pub enum PartContent {
+    Memory(Vec<u8>),
+    /// The temp file is deleted when this is dropped, unless it is [persisted](PartContent::persist).
+    File {
+        file: NamedTempFile,
+        length: u64,
+    },
 }
 
-pub type MultipartParts = HashMap<String, MultipartPart>;
+impl Default for PartContent {
+    fn default() -> Self {
+        PartContent::Memory(Vec::new())
+    }
+}
+
+impl PartContent {
+    pub fn len(&self) -> u64 {
+        match self {
+            PartContent::Memory(bytes) => bytes.len() as u64,
+            PartContent::File {
+                length,
+                ..
+            } => *length,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-#[derive(Default)]
-struct MultipartParser {
-    parts: HashMap<String, MultipartPart>,
-    // boundary: String,
-    state: MultipartParserState,
+    /// Loads the full content into memory.
+    pub async fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            PartContent::Memory(bytes) => Ok(bytes),
+            PartContent::File {
+                file,
+                ..
+            } => tokio::fs::read(file.path()).await,
+        }
+    }
+
+    /// Like [PartContent::into_bytes], for synchronous code. Reading a spooled part blocks, so the runtime
+    /// is told to move its other tasks off of this thread first.
+    pub fn into_bytes_blocking(self) -> io::Result<Vec<u8>> {
+        match self {
+            PartContent::Memory(bytes) => Ok(bytes),
+            PartContent::File {
+                file,
+                ..
+            } => block_in_place(|| std::fs::read(file.path())),
+        }
+    }
+
+    /// Saves the content to `path`. Spooled content is moved there instead of being copied, when possible.
+    pub async fn persist(
+        self,
+        path: impl AsRef<Path>,
+    ) -> io::Result<()> {
+        match self {
+            PartContent::Memory(bytes) => tokio::fs::write(path, bytes).await,
+            PartContent::File {
+                file,
+                ..
+            } => match file.persist(path.as_ref()) {
+                Ok(_) => Ok(()),
+                // Temp files can't be renamed across filesystems, so fall back to a copy.
+                Err(err) => tokio::fs::copy(err.file.path(), path).await.map(|_| ()),
+            },
+        }
+    }
 }
 
-fn check_boundary(
-    bytes: &[u8],
-    boundary: &str,
-) -> BoundaryMatch {
-    let is_boundary = bytes.len() >= boundary.len() + 4 // To avoid out of bounds panic. 4 = len("----") AND len("--\r\n"). It's a bit of a hack.
-        && bytes[0..2] == *b"--" // Must start with --
-        && bytes[2..boundary.len() + 2] == *boundary.as_bytes() // Check the boundary
-    ;
-    if is_boundary {
-        let is_end_boundary = bytes[boundary.len() + 2..boundary.len() + 4] == *b"--";
-        if is_end_boundary {
-            BoundaryMatch::EndBoundary
-        } else {
-            BoundaryMatch::Boundary
+/// Runs blocking work from synchronous code on a runtime thread. The multi-threaded runtime can hand
+/// this thread's other tasks off while it blocks, but the single-threaded one can't, so it just runs.
+fn block_in_place<T>(f: impl FnOnce() -> T) -> T {
+    match tokio::runtime::Handle::try_current().map(|handle| handle.runtime_flavor()) {
+        Ok(RuntimeFlavor::MultiThread) => tokio::task::block_in_place(f),
+        _ => f(),
+    }
+}
+
+/// The parts of a `multipart/form-data` body, in the order they were sent.
+/// Field names can repeat (e.g. for `<input type="file" multiple>`), so every part is kept.
+#[derive(Debug, Default)]
+pub struct MultipartParts(Vec<MultipartPart>);
+
+impl MultipartParts {
+    /// Gets the first part with the given name.
+    pub fn get(
+        &self,
+        name: &str,
+    ) -> Option<&MultipartPart> {
+        self.0.iter().find(|part| part.name == name)
+    }
+
+    /// Gets every part with the given name.
+    pub fn get_all<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a MultipartPart> {
+        self.0.iter().filter(move |part| part.name == name)
+    }
+
+    /// Removes and returns the first part with the given name.
+    pub fn remove(
+        &mut self,
+        name: &str,
+    ) -> Option<MultipartPart> {
+        let index = self.0.iter().position(|part| part.name == name)?;
+        Some(self.0.remove(index))
+    }
+
+    /// Removes and returns every part with the given name.
+    pub fn remove_all(
+        &mut self,
+        name: &str,
+    ) -> Vec<MultipartPart> {
+        let (removed, kept) =
+            std::mem::take(&mut self.0).into_iter().partition(|part| part.name == name);
+        self.0 = kept;
+        removed
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MultipartPart> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl IntoIterator for MultipartParts {
+    type Item = MultipartPart;
+    type IntoIter = std::vec::IntoIter<MultipartPart>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+/// Size limits for reading a multipart body. The defaults come from [ConfigConstants].
+#[derive(Debug, Clone)]
+pub struct MultipartLimits {
+    /// The maximum length of the whole body.
+    pub max_length: u64,
+    /// The maximum length of a single part's content.
+    pub max_part_length: u64,
+    /// Parts longer than this are spooled to a temp file instead of being kept in memory.
+    pub spill_threshold: u64,
+    /// The maximum content kept in memory across all parts. Any parts past this are spooled to disk,
+    /// regardless of their size.
+    pub max_memory: u64,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        Self {
+            max_length: ConfigConstants::multipart_max_length(),
+            max_part_length: ConfigConstants::multipart_max_part_length(),
+            spill_threshold: ConfigConstants::multipart_spill_threshold(),
+            max_memory: ConfigConstants::max_content_length(),
         }
-    } else {
-        BoundaryMatch::None
     }
 }
 
-//Parses the body of a multi_part_request into parts.
-pub fn parse_multipart_request(
+/// Reads a `multipart/form-data` body (Ref: RFC 7578) incrementally off of the stream, so only the
+/// current window of the body is held in memory.
+///
+/// The stream must end with the body (e.g. with `take(content_length)`), since anything after the
+/// close delimiter is read as the epilogue.
+pub async fn read_multipart_body<R: AsyncRead + Unpin>(
+    stream: &mut R,
     content_type_params: &str,
-    bytes: Vec<u8>,
-) -> Result<HttpBody, crate::Error> {
+    limits: &MultipartLimits,
+) -> HttpResult<MultipartParts> {
     let parsed = Headers::parse_params(content_type_params);
     let boundary = parsed
         .get("boundary")
+        .map(|boundary| boundary.trim_matches('"'))
         .ok_or("No boundary defined. Required for multipart requests.")?;
+    // Ref: RFC 2046 5.1.1
+    if boundary.is_empty() || boundary.len() > 70 {
+        crate::Error::bad_request("Invalid multipart boundary.")?;
+    }
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
 
-    let mut parser: MultipartParser = MultipartParser::default();
-    let mut stream = BufReader::new(&*bytes);
+    let mut reader = MultipartReader {
+        stream,
+        buf: Vec::new(),
+        read: 0,
+        max_length: limits.max_length,
+    };
 
-    let mut chunk: Vec<u8> = Vec::new();
-    let mut part = MultipartPart::default();
+    // Skip the preamble, up to the first boundary.
+    loop {
+        let line = reader.next_line().await?;
+        if String::from_utf8_lossy(&line).trim_end().as_bytes() == &delimiter[2..] {
+            break;
+        }
+    }
 
-    while stream.read_until(b'\n', &mut chunk)? > 0 {
-        match parser.state {
-            MultipartParserState::Start => {
-                if !matches!(check_boundary(&chunk, boundary), BoundaryMatch::Boundary) {
-                    Err("Failed to find boundary at start of message.")?
-                }
-                parser.state = MultipartParserState::Headers;
-            },
-            MultipartParserState::Headers => {
-                // Read headers
-                let header = String::from_utf8_lossy(&chunk);
-                let header = header.trim();
-                if header.is_empty() {
-                    parser.state = MultipartParserState::Body;
-                } else {
-                    let (name, value) = part.headers.insert_parsed(header)?;
-                    if name == "content-disposition" {
-                        part.name = value.get_param("name").map(|s| s.to_string()).ok_or(
-                            crate::Error::BadRequest(
-                                "Found unnamed multipart/form-data field.".into(),
-                            ),
-                        )?;
-                    }
-                }
-            },
-            MultipartParserState::Body => match check_boundary(&chunk, boundary) {
-                BoundaryMatch::None => part.content.append(&mut chunk),
-                BoundaryMatch::Boundary => {
-                    parser.parts.insert(part.name.clone(), part);
-                    parser.state = MultipartParserState::Headers;
-                    part = MultipartPart::default();
-                },
-                BoundaryMatch::EndBoundary => {
-                    parser.parts.insert(part.name.clone(), part);
-                    part = MultipartPart::default();
-                    parser.state = MultipartParserState::Done;
-                },
-            },
-            MultipartParserState::Done => panic!(
-                "Continued receiving data after end of parser. This is an inconsistent state."
-            ),
+    let mut parts = Vec::new();
+    let mut in_memory = 0;
+    loop {
+        let mut part = MultipartPart::default();
+        let mut headers_length = 0;
+        loop {
+            let line = reader.next_line().await?;
+            headers_length += line.len() as u64;
+            if headers_length > ConfigConstants::headers_max_length() {
+                crate::Error::bad_request("Multipart headers are too long.")?;
+            }
+            let header = String::from_utf8_lossy(&line);
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            let (name, value) = part.headers.insert_parsed(header)?;
+            if name == "content-disposition" {
+                part.name = value.get_param("name").map(|s| s.to_string()).ok_or(
+                    crate::Error::BadRequest("Found unnamed multipart/form-data field.".into()),
+                )?;
+            }
         }
-        chunk = Vec::new();
+
+        let mut content = ContentWriter {
+            spool: Spool::Memory(Vec::new()),
+            length: 0,
+            max_length: limits.max_part_length,
+            spill_threshold: limits
+                .spill_threshold
+                .min(limits.max_memory.saturating_sub(in_memory)),
+        };
+        reader.read_content(&delimiter, &mut content).await?;
+        part.content = content.finish().await?;
+        if let PartContent::Memory(bytes) = &part.content {
+            in_memory += bytes.len() as u64;
+        }
+        parts.push(part);
+
+        // The close delimiter ends with `--`. Anything else up to the CRLF is transport padding.
+        if reader.next_is_close_delimiter().await? {
+            break;
+        }
+        reader.next_line().await?;
     }
     log::debug!("Finished parsing multipart request!");
 
-    Ok(HttpBody::Multipart(parser.parts))
+    Ok(MultipartParts(parts))
+}
+
+/// The `Content-Type` params (i.e. the boundary) of a `multipart/form-data` body that can be parsed
+/// straight off of the stream. Compressed bodies have to be decoded first, so they aren't.
+pub(crate) fn streamed_multipart_params(headers: &Headers) -> Option<String> {
+    let content_type = headers.get("content-type")?.as_str();
+    let (media_type, params) = content_type.split_once(';').unwrap_or((content_type, ""));
+    let is_multipart = media_type.trim().eq_ignore_ascii_case("multipart/form-data");
+    (is_multipart && !is_content_encoded(headers)).then(|| params.to_string())
+}
+
+/// Reads a multipart body from a stream that ends with it (see [read_multipart_body]), then reads the
+/// rest of the stream, so that the connection is left at the start of the next request.
+pub(crate) async fn read_streamed_multipart<R: AsyncRead + Unpin>(
+    mut body: R,
+    content_type_params: &str,
+    limits: &MultipartLimits,
+) -> HttpResult<HttpBody> {
+    let parts = read_multipart_body(&mut body, content_type_params, limits).await?;
+    // The epilogue is ignored. Ref: RFC 2046 5.1.1
+    tokio::io::copy(&mut body, &mut tokio::io::sink()).await?;
+    Ok(HttpBody::Multipart(parts))
+}
+
+struct MultipartReader<'a, R> {
+    stream: &'a mut R,
+    buf: Vec<u8>,
+    read: u64,
+    max_length: u64,
+}
+
+impl<R: AsyncRead + Unpin> MultipartReader<'_, R> {
+    /// Reads more of the body into the buffer. Fails if the body ends first.
+    async fn fill(&mut self) -> HttpResult<()> {
+        let start = self.buf.len();
+        self.buf.resize(start + READ_SIZE, 0);
+        let read = self.stream.read(&mut self.buf[start..]).await?;
+        self.buf.truncate(start + read);
+
+        self.read += read as u64;
+        if self.read > self.max_length {
+            crate::Error::entity_too_large()?;
+        }
+        if read == 0 {
+            crate::Error::bad_request("Multipart body ended before the close delimiter.")?;
+        }
+        Ok(())
+    }
+
+    /// Takes the next line, including its line ending, off of the buffer.
+    async fn next_line(&mut self) -> HttpResult<Vec<u8>> {
+        loop {
+            if let Some(end) = self.buf.iter().position(|byte| *byte == b'\n') {
+                return Ok(self.buf.drain(..=end).collect());
+            }
+            if self.buf.len() as u64 > ConfigConstants::headers_max_length() {
+                crate::Error::bad_request("Multipart line is too long.")?;
+            }
+            self.fill().await?;
+        }
+    }
+
+    async fn next_is_close_delimiter(&mut self) -> HttpResult<bool> {
+        while self.buf.len() < 2 {
+            self.fill().await?;
+        }
+        Ok(self.buf.starts_with(b"--"))
+    }
+
+    /// Writes everything up to the next delimiter into `content`, and consumes the delimiter.
+    async fn read_content(
+        &mut self,
+        delimiter: &[u8],
+        content: &mut ContentWriter,
+    ) -> HttpResult<()> {
+        loop {
+            if let Some(position) = self.buf.windows(delimiter.len()).position(|w| w == delimiter) {
+                content.write(&self.buf[..position]).await?;
+                self.buf.drain(..position + delimiter.len());
+                return Ok(());
+            }
+            // Hold back anything that could be the start of a delimiter split across reads.
+            let complete = self.buf.len().saturating_sub(delimiter.len() - 1);
+            content.write(&self.buf[..complete]).await?;
+            self.buf.drain(..complete);
+            self.fill().await?;
+        }
+    }
+}
+
+enum Spool {
+    Memory(Vec<u8>),
+    File(NamedTempFile, tokio::fs::File),
+}
+
+/// Collects a part's content, moving it to a temp file once it grows past the spill threshold.
+struct ContentWriter {
+    spool: Spool,
+    length: u64,
+    max_length: u64,
+    spill_threshold: u64,
+}
+
+impl ContentWriter {
+    async fn write(
+        &mut self,
+        bytes: &[u8],
+    ) -> HttpResult<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        self.length += bytes.len() as u64;
+        if self.length > self.max_length {
+            crate::Error::entity_too_large()?;
+        }
+
+        if let Spool::Memory(content) = &mut self.spool {
+            if self.length <= self.spill_threshold {
+                content.extend_from_slice(bytes);
+                return Ok(());
+            }
+            let file = NamedTempFile::new()?;
+            let mut writer = tokio::fs::File::from_std(file.as_file().try_clone()?);
+            writer.write_all(content).await?;
+            self.spool = Spool::File(file, writer);
+        }
+        if let Spool::File(_, writer) = &mut self.spool {
+            writer.write_all(bytes).await?;
+        }
+        Ok(())
+    }
+
+    async fn finish(self) -> HttpResult<PartContent> {
+        match self.spool {
+            Spool::Memory(content) => Ok(PartContent::Memory(content)),
+            Spool::File(file, mut writer) => {
+                writer.flush().await?;
+                Ok(PartContent::File {
+                    file,
+                    length: self.length,
+                })
+            },
+        }
+    }
 }
 
 pub trait FromMultipartPart {
@@ -143,3 +464,59 @@ impl TryGetMultipartParts for Request {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::{read_multipart_body, MultipartLimits, PartContent};
+
+    const BODY: &[u8] = b"preamble\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"tag\"\r\n\r\nfirst\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"tag\"\r\n\r\nsecond\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\nline one\r\n--XY not a boundary\r\n--XyZ--\r\nepilogue";
+
+    fn limits() -> MultipartLimits {
+        MultipartLimits {
+            max_length: 1024,
+            max_part_length: 64,
+            spill_threshold: 16,
+            max_memory: 1024,
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_repeated_fields_and_spools_large_parts() {
+        // Split the body in the middle of a delimiter, to make sure it is still found.
+        let split = BODY.windows(7).position(|w| w == b"--XyZ--").unwrap() + 3;
+        let mut stream = (&BODY[..split]).chain(&BODY[split..]);
+
+        let mut parts = read_multipart_body(&mut stream, " boundary=XyZ", &limits()).await.unwrap();
+
+        assert_eq!(parts.get_all("tag").count(), 2);
+        assert_eq!(parts.remove("tag").unwrap().text().await.unwrap(), "first");
+        assert_eq!(parts.remove("tag").unwrap().text_blocking().unwrap(), "second");
+
+        let file = parts.remove("file").unwrap();
+        assert_eq!(file.filename().as_deref(), Some("a.txt"));
+        assert!(matches!(file.content, PartContent::File { .. }));
+        assert_eq!(file.text().await.unwrap(), "line one\r\n--XY not a boundary");
+        assert!(parts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn enforces_size_limits() {
+        let part_limit = MultipartLimits {
+            max_part_length: 8,
+            ..limits()
+        };
+        assert!(read_multipart_body(&mut &BODY[..], "boundary=XyZ", &part_limit).await.is_err());
+
+        let total_limit = MultipartLimits {
+            max_length: 64,
+            ..limits()
+        };
+        assert!(read_multipart_body(&mut &BODY[..], "boundary=XyZ", &total_limit).await.is_err());
+    }
+}
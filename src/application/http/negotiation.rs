@@ -0,0 +1,380 @@
+use std::{future::Future, sync::Arc};
+
+use serde::{
+    de::{DeserializeOwned, Error as _},
+    Serialize,
+};
+
+use crate::errors::invalid_field;
+
+use super::{
+    route::Response,
+    serializers::{Cbor, ContentSerializer, Csv, Json, MessagePack, PrettyJson, Yaml},
+};
+
+/// A single media range from an `Accept` header, e.g. `text/*;q=0.5`. Ref: RFC 9110 12.5.1
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaRange {
+    /// The lowercased `type/subtype`, either of which may be `*`.
+    pub media_type: String,
+    pub params: Vec<(String, String)>,
+    pub quality: f32,
+}
+
+impl MediaRange {
+    /// Matches everything, as if the client had sent no `Accept` header.
+    pub fn any() -> Self {
+        Self {
+            media_type: "*/*".into(),
+            params: Vec::new(),
+            quality: 1.0,
+        }
+    }
+
+    /// Parses an `Accept` header into its media ranges, skipping any that are malformed.
+    pub fn parse_accept(accept: &str) -> Vec<Self> {
+        accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let media_type = parts.next()?.trim().to_lowercase();
+                if !media_type.contains('/') {
+                    return None;
+                }
+                let mut quality = 1.0;
+                let mut params = Vec::new();
+                for param in parts {
+                    let (name, value) = param.split_once('=').unwrap_or((param, ""));
+                    let name = name.trim().to_lowercase();
+                    let value = value.trim().trim_matches('"').to_string();
+                    if name == "q" {
+                        quality = value.parse().ok().filter(|q| (0.0..=1.0).contains(q))?;
+                    } else {
+                        params.push((name, value));
+                    }
+                }
+                Some(Self {
+                    media_type,
+                    params,
+                    quality,
+                })
+            })
+            .collect()
+    }
+
+    /// Whether this range covers the given media type, e.g. `image/*` covers `image/png`.
+    pub fn matches(
+        &self,
+        media_type: &str,
+    ) -> bool {
+        let (range_type, range_subtype) =
+            self.media_type.split_once('/').unwrap_or((&self.media_type, ""));
+        let (media_type, media_subtype) = media_type.split_once('/').unwrap_or((media_type, ""));
+        (range_type == "*" || range_type.eq_ignore_ascii_case(media_type))
+            && (range_subtype == "*" || range_subtype.eq_ignore_ascii_case(media_subtype))
+    }
+
+    pub fn param(
+        &self,
+        name: &str,
+    ) -> Option<&str> {
+        self.params.iter().find(|(param, _)| param == name).map(|(_, value)| value.as_str())
+    }
+
+    /// More specific ranges take precedence over less specific ones that match the same media type.
+    fn specificity(&self) -> u8 {
+        match self.media_type.as_str() {
+            "*/*" => 0,
+            media_type if media_type.ends_with("/*") => 1,
+            _ if self.params.is_empty() => 2,
+            _ => 3,
+        }
+    }
+}
+
+/// The registry of serializers used to write response bodies and read request bodies.
+///
+/// Responses use the serializer that best matches the request's `Accept` header. When several are
+/// equally acceptable (e.g. for `*/*`), the one registered first wins, so JSON stays the default.
+#[derive(Clone)]
+pub struct Serializers(Vec<Arc<dyn ContentSerializer>>);
+
+impl Default for Serializers {
+    /// JSON, pretty JSON, CBOR, MessagePack, CSV (for lists), and YAML.
+    fn default() -> Self {
+        Self::empty().with(Json).with(PrettyJson).with(Cbor).with(MessagePack).with(Csv).with(Yaml)
+    }
+}
+
+impl Serializers {
+    pub fn empty() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Registers another serializer, after the existing ones.
+    pub fn with(
+        mut self,
+        serializer: impl ContentSerializer + 'static,
+    ) -> Self {
+        self.0.push(Arc::new(serializer));
+        self
+    }
+
+    /// The serializers acceptable for the given media ranges, most preferred first.
+    pub fn preferred(
+        &self,
+        accept: &[MediaRange],
+    ) -> Vec<&dyn ContentSerializer> {
+        let any = [MediaRange::any()];
+        let accept = if accept.is_empty() { &any[..] } else { accept };
+
+        let mut candidates: Vec<_> = self
+            .0
+            .iter()
+            .enumerate()
+            .filter_map(|(index, serializer)| {
+                // The most specific range that matches decides the quality. Ref: RFC 9110 12.5.1
+                let range = accept
+                    .iter()
+                    .filter(|range| serializer.accepts(range))
+                    .max_by_key(|range| range.specificity())?;
+                let quality = (range.quality * 1000.0) as u16;
+                (quality > 0).then_some((quality, range.specificity(), index, serializer))
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)).then(a.2.cmp(&b.2)));
+        candidates.into_iter().map(|(_, _, _, serializer)| serializer.as_ref()).collect()
+    }
+
+    /// The serializer that reads request bodies of the given media type (without parameters).
+    pub fn for_content_type(
+        &self,
+        content_type: &str,
+    ) -> Option<&dyn ContentSerializer> {
+        self.0
+            .iter()
+            .find(|serializer| {
+                serializer.media_types().iter().any(|media_type| media_type == &content_type)
+            })
+            .map(|serializer| serializer.as_ref())
+    }
+
+    /// Serializes a response body in the most preferred representation that can express it.
+    /// Responds with `406 Not Acceptable` if there isn't one.
+    pub fn respond(
+        &self,
+        accept: &[MediaRange],
+        value: &dyn erased_serde::Serialize,
+    ) -> Response {
+        for serializer in self.preferred(accept) {
+            match serializer.serialize(value) {
+                Ok(body) => {
+                    return Response::ok()
+                        .with_body(body)
+                        .with_header("Content-Type", serializer.media_types()[0])
+                        .with_header("Vary", "Accept");
+                },
+                Err(crate::Error::NotAcceptable) => continue,
+                Err(err) => {
+                    log::error!("Failed to serialize response: {:?}", err);
+                    return Response::internal_server_error();
+                },
+            }
+        }
+        Response::not_acceptable().with_header("Vary", "Accept")
+    }
+
+    /// Deserializes a request body with the given serializer. If a single field can't be read, the
+    /// error says which one.
+    pub fn deserialize<T: DeserializeOwned>(
+        serializer: &dyn ContentSerializer,
+        bytes: &[u8],
+    ) -> Result<T, crate::Error> {
+        let mut value = None;
+        let mut field_error = None;
+        let result = serializer.deserialize(
+            bytes,
+            &mut |deserializer: &mut dyn erased_serde::Deserializer<'_>| {
+                match serde_path_to_error::deserialize(deserializer) {
+                    Ok(deserialized) => value = Some(deserialized),
+                    Err(err) => {
+                        let inner = erased_serde::Error::custom(err.inner());
+                        field_error = Some(invalid_field(err, "Invalid request body"));
+                        return Err(inner);
+                    },
+                }
+                Ok(())
+            },
+        );
+        match (result, field_error) {
+            (Err(_), Some(field_error)) => Err(field_error),
+            (Err(err), None) => Err(err),
+            (Ok(()), _) => value
+                .ok_or_else(|| crate::Error::BadRequest("Request body could not be read.".into())),
+        }
+    }
+}
+
+/// The serializers and preferences in effect while a request is handled.
+struct Negotiation {
+    serializers: Serializers,
+    accept: Vec<MediaRange>,
+}
+
+tokio::task_local! {
+    static NEGOTIATION: Negotiation;
+}
+
+/// Runs a handler with the request's `Accept` header in scope, so that anything it returns is
+/// serialized in the representation the client asked for.
+pub(crate) async fn negotiate<F: Future>(
+    serializers: Serializers,
+    accept: Option<&str>,
+    handler: F,
+) -> F::Output {
+    let negotiation = Negotiation {
+        serializers,
+        accept: accept.map(MediaRange::parse_accept).unwrap_or_default(),
+    };
+    NEGOTIATION.scope(negotiation, handler).await
+}
+
+/// Serializes a value into a response, according to the current request's `Accept` header.
+/// Outside of a request (e.g. in middleware), this falls back to the default serializers.
+pub fn serialize_response<T: Serialize>(value: &T) -> Response {
+    NEGOTIATION
+        .try_with(|negotiation| negotiation.serializers.respond(&negotiation.accept, value))
+        .unwrap_or_else(|_| Serializers::default().respond(&[], value))
+}
+
+/// Deserializes a request body of the given media type, if a serializer is registered for it.
+pub(crate) fn deserialize_body<T: DeserializeOwned>(
+    content_type: &str,
+    bytes: &[u8],
+) -> Option<Result<T, crate::Error>> {
+    let deserialize = |serializers: &Serializers| {
+        serializers
+            .for_content_type(content_type)
+            .map(|serializer| Serializers::deserialize(serializer, bytes))
+    };
+    NEGOTIATION
+        .try_with(|negotiation| deserialize(&negotiation.serializers))
+        .unwrap_or_else(|_| deserialize(&Serializers::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::{MediaRange, Serializers};
+    use crate::application::http::response_body::ResponseBody;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Event {
+        id: u32,
+        name: String,
+    }
+
+    fn preferred(accept: &str) -> Vec<&'static str> {
+        Serializers::default()
+            .preferred(&MediaRange::parse_accept(accept))
+            .into_iter()
+            .map(|serializer| serializer.media_types()[0])
+            .collect()
+    }
+
+    #[test]
+    fn prefers_quality_then_specificity_then_registration() {
+        assert_eq!(preferred("")[0], "application/json");
+        assert_eq!(preferred("*/*")[0], "application/json");
+        assert_eq!(preferred("application/cbor, */*")[0], "application/cbor");
+        assert_eq!(preferred("application/json;q=0.5, application/x-yaml")[0], "application/yaml");
+        assert_eq!(preferred("text/*, application/json;q=0"), vec!["text/csv", "application/yaml"]);
+        assert!(preferred("image/png").is_empty());
+    }
+
+    #[test]
+    fn picks_pretty_json_when_asked_for() {
+        let serializers = Serializers::default();
+        let event = Event {
+            id: 1,
+            name: "Launch".into(),
+        };
+        let body = |accept: &str| {
+            let response = serializers.respond(&MediaRange::parse_accept(accept), &event);
+            match response.body {
+                ResponseBody::Bytes(body) => String::from_utf8(body).unwrap(),
+                ResponseBody::Stream(_) => panic!("Expected a buffered body"),
+            }
+        };
+
+        assert!(body("application/json; pretty").contains("\n  \"id\": 1"));
+        assert!(body("application/json; pretty=true").contains('\n'));
+        assert!(!body("application/json; pretty=false").contains('\n'));
+        assert!(!body("application/json").contains('\n'));
+    }
+
+    #[test]
+    fn falls_back_when_a_list_is_required() {
+        let serializers = Serializers::default();
+        let event = Event {
+            id: 1,
+            name: "Launch".into(),
+        };
+
+        let response = serializers.respond(&MediaRange::parse_accept("text/csv"), &event);
+        assert_eq!(response.status as usize, 406);
+
+        let accept = MediaRange::parse_accept("text/csv, application/json;q=0.1");
+        let response = serializers.respond(&accept, &event);
+        assert_eq!(
+            response.headers.get("content-type").map(|h| h.as_str()),
+            Some("application/json")
+        );
+    }
+
+    #[test]
+    fn round_trips_every_format() {
+        let serializers = Serializers::default();
+        let events = vec![
+            Event {
+                id: 1,
+                name: "Launch".into(),
+            },
+            Event {
+                id: 2,
+                name: "Landing, maybe".into(),
+            },
+        ];
+        for media_type in [
+            "application/json",
+            "application/cbor",
+            "application/msgpack",
+            "text/csv",
+            "application/yaml",
+        ] {
+            let serializer = serializers.for_content_type(media_type).unwrap();
+            let bytes = serializer.serialize(&events).unwrap();
+            let decoded: Vec<Event> = Serializers::deserialize(serializer, &bytes).unwrap();
+            assert_eq!(decoded, events, "{}", media_type);
+        }
+    }
+
+    #[test]
+    fn keeps_csv_columns_in_field_order() {
+        #[derive(Serialize)]
+        struct Shipment {
+            name: &'static str,
+            id: u32,
+            arrival: &'static str,
+        }
+        let shipments = vec![Shipment {
+            name: "Parts",
+            id: 7,
+            arrival: "2024-05-01",
+        }];
+        let csv = Serializers::default().for_content_type("text/csv").unwrap();
+        let bytes = csv.serialize(&shipments).unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(), "name,id,arrival\nParts,7,2024-05-01\n");
+    }
+}
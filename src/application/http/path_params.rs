@@ -0,0 +1,373 @@
+use std::{fmt::Display, ops::Deref, str::FromStr};
+
+use serde::{
+    de::{
+        value::{MapDeserializer, SeqDeserializer},
+        DeserializeOwned, IntoDeserializer, Visitor,
+    },
+    forward_to_deserialize_any, Deserializer,
+};
+
+use super::route::{FromRequest, Request};
+use crate::errors::deserialize_tracked;
+
+/// The path parameters captured while routing a request, in the order they appear in the path.
+/// The names come from the route definition, e.g. `/org/{org_id}/event/{event_id}`.
+#[derive(Debug, Default, Clone)]
+pub struct PathParams(Vec<(String, String)>);
+
+impl PathParams {
+    /// Gets the (decoded) value of the named parameter.
+    pub fn get(
+        &self,
+        name: &str,
+    ) -> Option<&str> {
+        self.0.iter().find(|(param, _)| param == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Deserializes the parameters into `T`. Scalars are read from the first parameter, tuples from the
+    /// parameters in order, and structs / maps by parameter name. Values are parsed with `FromStr`.
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, crate::Error> {
+        deserialize_tracked(ParamsDeserializer(&self.0), "Unable to extract path parameters")
+    }
+}
+
+impl FromIterator<(String, String)> for PathParams {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+/// An extractor that deserializes all path parameters into a struct, by name.
+///
+/// ```ignore
+/// #[derive(Deserialize)]
+/// struct EventPath {
+///     org_id: Uuid,
+///     event_id: Uuid,
+/// }
+///
+/// // Routed at `/org/{org_id}/event/{event_id}`
+/// async fn get_event(Path(path): Path<EventPath>) -> Response { ... }
+/// ```
+pub struct Path<T>(pub T);
+
+impl<T> Deref for Path<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for Path<T> {
+    fn from(req: Request) -> Result<Self, crate::Error> {
+        req.path_params.deserialize().map(Path)
+    }
+}
+
+#[derive(Debug)]
+pub struct PathParamError(String);
+
+impl Display for PathParamError {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for PathParamError {}
+
+impl serde::de::Error for PathParamError {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+/// Deserializes the full set of path parameters. Also used for other name / value rows, e.g. CSV records.
+pub(crate) struct ParamsDeserializer<'de>(pub(crate) &'de [(String, String)]);
+
+impl<'de> IntoDeserializer<'de, PathParamError> for ParamsDeserializer<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+impl<'de> ParamsDeserializer<'de> {
+    fn first(&self) -> Result<ValueDeserializer<'de>, PathParamError> {
+        self.0
+            .first()
+            .map(|(_, value)| ValueDeserializer(value))
+            .ok_or_else(|| PathParamError("No path parameters were found".into()))
+    }
+
+    fn values(
+        &self
+    ) -> SeqDeserializer<impl Iterator<Item = ValueDeserializer<'de>>, PathParamError> {
+        SeqDeserializer::new(self.0.iter().map(|(_, value)| ValueDeserializer(value)))
+    }
+}
+
+/// Scalars are read from the first parameter.
+macro_rules! forward_to_first {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(
+                self,
+                visitor: V,
+            ) -> Result<V::Value, Self::Error> {
+                self.first()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for ParamsDeserializer<'de> {
+    type Error = PathParamError;
+
+    forward_to_first!(
+        deserialize_any deserialize_bool deserialize_char deserialize_str deserialize_string
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128
+        deserialize_f32 deserialize_f64 deserialize_bytes deserialize_byte_buf deserialize_unit
+        deserialize_identifier deserialize_ignored_any
+    );
+
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        if self.0.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let mut values = self.values();
+        let seq = visitor.visit_seq(&mut values)?;
+        values.end()?;
+        Ok(seq)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let mut params = MapDeserializer::new(
+            self.0.iter().map(|(name, value)| (name.as_str(), ValueDeserializer(value))),
+        );
+        let map = visitor.visit_map(&mut params)?;
+        params.end()?;
+        Ok(map)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.first()?.deserialize_enum(name, variants, visitor)
+    }
+}
+
+/// Deserializes a single path parameter, parsing it into the requested type with `FromStr`.
+struct ValueDeserializer<'de>(&'de str);
+
+impl<'de> ValueDeserializer<'de> {
+    fn parse<T>(&self) -> Result<T, PathParamError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.0.parse().map_err(|err| PathParamError(format!("Invalid value '{}': {}", self.0, err)))
+    }
+}
+
+impl<'de> IntoDeserializer<'de, PathParamError> for ValueDeserializer<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(
+                self,
+                visitor: V,
+            ) -> Result<V::Value, Self::Error> {
+                visitor.$visit(self.parse()?)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = PathParamError;
+
+    deserialize_parsed!(
+        deserialize_bool => visit_bool,
+        deserialize_char => visit_char,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    );
+
+    fn deserialize_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        // Matched path parameters are never empty, but (CSV) cells can be.
+        if self.0.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        // Only unit variants can be expressed in a path segment.
+        <&str as IntoDeserializer<'de, PathParamError>>::into_deserializer(self.0)
+            .deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use uuid::Uuid;
+
+    use super::PathParams;
+
+    fn params(values: &[(&str, &str)]) -> PathParams {
+        values.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn deserializes_scalars_tuples_and_structs() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct EventPath {
+            org_id: Uuid,
+            page: u32,
+        }
+        let org_id = Uuid::new_v4();
+        let params = params(&[("org_id", &org_id.to_string()), ("page", "3")]);
+
+        assert_eq!(params.deserialize::<Uuid>().unwrap(), org_id);
+        assert_eq!(params.deserialize::<(Uuid, u32)>().unwrap(), (org_id, 3));
+        assert_eq!(
+            params.deserialize::<EventPath>().unwrap(),
+            EventPath {
+                org_id,
+                page: 3
+            }
+        );
+        assert_eq!(params.get("page"), Some("3"));
+    }
+
+    #[test]
+    fn rejects_unparseable_values() {
+        let params = params(&[("id", "not-a-number")]);
+        assert!(params.deserialize::<u32>().is_err());
+        assert!(params.deserialize::<(u32, u32)>().is_err());
+    }
+}
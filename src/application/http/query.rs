@@ -0,0 +1,73 @@
+use std::{collections::HashMap, ops::Deref};
+
+use serde::de::DeserializeOwned;
+
+use super::{
+    form::deserialize_urlencoded,
+    route::{FromRequestContext, RequestContext},
+};
+
+/// The raw (still percent-encoded) query string of the current request, stored in the request context
+/// so that extractors can get to it after the request itself has been consumed.
+pub(crate) struct QueryString(pub(crate) String);
+
+/// Splits a request target (e.g. `/events?limit=10`) into its path and raw query string.
+pub fn split_request_target(target: &str) -> (&str, &str) {
+    target.split_once('?').unwrap_or((target, ""))
+}
+
+/// Parses a raw query string into a map of decoded names and values.
+/// If a name is given more than once, the last value wins.
+pub fn parse_query_params(query: &str) -> HashMap<String, String> {
+    serde_urlencoded::from_str(query).unwrap_or_else(|err| {
+        log::warn!("Unable to parse query string {}: {}", query, err);
+        HashMap::new()
+    })
+}
+
+/// An extractor that deserializes the query string into `T`.
+/// Works alongside body and path extractors, e.g. `fn(PathVariable<Uuid>, Query<Pagination>)`.
+///
+/// ```ignore
+/// #[derive(Deserialize)]
+/// struct Pagination {
+///     limit: Option<usize>,
+///     offset: Option<usize>,
+/// }
+///
+/// async fn list_events(Query(page): Query<Pagination>, events: PostgresDataProvider<Event>) -> Vec<Event> {
+///     ...
+/// }
+/// ```
+pub struct Query<T>(pub T);
+
+impl<T> Deref for Query<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned> FromRequestContext for Query<T> {
+    fn from_request_context(ctx: &RequestContext) -> Result<Self, crate::Error> {
+        let query = ctx.get_request_data::<QueryString>().map_or("", |query| query.0.as_str());
+        deserialize_urlencoded(query, "Invalid query string").map(Query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_query_params, split_request_target};
+
+    #[test]
+    fn splits_and_decodes_query() {
+        let (path, query) = split_request_target("/event?limit=10&name=hello%20world&tag=a+b");
+        assert_eq!(path, "/event");
+
+        let params = parse_query_params(query);
+        assert_eq!(params.get("limit").map(String::as_str), Some("10"));
+        assert_eq!(params.get("name").map(String::as_str), Some("hello world"));
+        assert_eq!(params.get("tag").map(String::as_str), Some("a b"));
+        assert_eq!(split_request_target("/event"), ("/event", ""));
+    }
+}
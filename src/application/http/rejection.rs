@@ -0,0 +1,140 @@
+use std::{marker::PhantomData, ops::Deref};
+
+use super::route::{
+    FromRequest, FromRequestContext, IntoResponse, Request, RequestContext, Response,
+};
+
+/// Wraps another extractor, sending `R` as the response when it fails. Works for body extractors
+/// ([FromRequest]) and ones that read the request context ([FromRequestContext]), like `Query`. The
+/// blanket extractor for `Deserialize` types can't be given its own rejection, so this is how to customize it.
+///
+/// ```ignore
+/// struct EventRejection(crate::Error);
+///
+/// impl From<crate::Error> for EventRejection {
+///     fn from(err: crate::Error) -> Self {
+///         EventRejection(err)
+///     }
+/// }
+///
+/// impl IntoResponse for EventRejection {
+///     fn into_response(self) -> Response {
+///         Response::bad_request().with_body(b"Events need a start and an end.".to_vec())
+///     }
+/// }
+///
+/// async fn create_event(event: WithRejection<Event, EventRejection>) -> Response { ... }
+/// async fn list_events(filter: WithRejection<Query<EventFilter>, EventRejection>) -> Response { ... }
+/// ```
+pub struct WithRejection<T, R>(pub T, PhantomData<R>);
+
+impl<T, R> WithRejection<T, R> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T, R> Deref for WithRejection<T, R> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T, R> FromRequest for WithRejection<T, R>
+where
+    T: FromRequest,
+    R: From<crate::Error> + IntoResponse,
+{
+    fn from(req: Request) -> Result<Self, crate::Error> {
+        T::from(req).map(|extracted| WithRejection(extracted, PhantomData))
+    }
+
+    fn rejection(err: crate::Error) -> Response {
+        R::from(err).into_response()
+    }
+}
+
+impl<T, R> FromRequestContext for WithRejection<T, R>
+where
+    T: FromRequestContext,
+    R: From<crate::Error> + IntoResponse,
+{
+    fn from_request_context(ctx: &RequestContext) -> Result<Self, crate::Error> {
+        T::from_request_context(ctx).map(|extracted| WithRejection(extracted, PhantomData))
+    }
+
+    fn rejection(err: crate::Error) -> Response {
+        R::from(err).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WithRejection;
+    use crate::application::http::{
+        body::tests::request,
+        form::deserialize_urlencoded,
+        query::Query,
+        response_body::ResponseBody,
+        route::{FromRequest, FromRequestContext, IntoResponse, Response},
+    };
+
+    #[derive(serde::Deserialize)]
+    #[allow(dead_code)]
+    struct Event {
+        start: u64,
+    }
+
+    struct EventRejection(crate::Error);
+
+    impl From<crate::Error> for EventRejection {
+        fn from(err: crate::Error) -> Self {
+            EventRejection(err)
+        }
+    }
+
+    impl IntoResponse for EventRejection {
+        fn into_response(self) -> Response {
+            let field = match self.0 {
+                crate::Error::InvalidField {
+                    field,
+                    ..
+                } => field,
+                _ => "body".into(),
+            };
+            Response::bad_request().with_body(format!("Check `{}`", field).into_bytes())
+        }
+    }
+
+    fn body(response: Response) -> Vec<u8> {
+        assert_eq!(response.status as usize, 400);
+        let ResponseBody::Bytes(body) = response.body else {
+            panic!("The rejection should be buffered");
+        };
+        body
+    }
+
+    #[tokio::test]
+    async fn sends_the_custom_rejection_for_bodies() {
+        type Extractor = WithRejection<Event, EventRejection>;
+        let json = |body: &'static str| request(Some("application/json"), body.as_bytes());
+        let event = <Extractor as FromRequest>::from(json(r#"{"start": 5}"#).await).unwrap();
+        assert_eq!(event.start, 5);
+
+        let Err(err) = <Extractor as FromRequest>::from(json(r#"{"start": "soon"}"#).await) else {
+            panic!("A string start should be rejected");
+        };
+        assert_eq!(body(<Extractor as FromRequest>::rejection(err)), b"Check `start`");
+    }
+
+    #[test]
+    fn sends_the_custom_rejection_for_context_extractors() {
+        type Extractor = WithRejection<Query<Event>, EventRejection>;
+        // The same error `Query` fails with for `?start=soon`.
+        let Err(err) = deserialize_urlencoded::<Event>("start=soon", "Invalid query string") else {
+            panic!("A non-numeric start should be rejected");
+        };
+        assert_eq!(body(<Extractor as FromRequestContext>::rejection(err)), b"Check `start`");
+    }
+}
@@ -0,0 +1,28 @@
+use std::future::Future;
+
+use uuid::Uuid;
+
+tokio::task_local! {
+    static REQUEST_ID: Uuid;
+}
+
+/// The id of the request being handled, as logged with `[REQ_ID ...]`. `None` outside of a request.
+pub fn current_request_id() -> Option<Uuid> {
+    REQUEST_ID.try_with(|id| *id).ok()
+}
+
+/// Runs a request's handler with its id in scope, so that errors and logs can refer back to it.
+pub(crate) async fn with_request_id<F: Future>(
+    request_id: Uuid,
+    handler: F,
+) -> F::Output {
+    REQUEST_ID.scope(request_id, handler).await
+}
+
+/// Like [with_request_id], for work that doesn't need to be awaited.
+pub(crate) fn sync_with_request_id<T>(
+    request_id: Uuid,
+    f: impl FnOnce() -> T,
+) -> T {
+    REQUEST_ID.sync_scope(request_id, f)
+}
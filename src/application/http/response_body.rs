@@ -0,0 +1,135 @@
+use std::pin::Pin;
+
+use futures::{Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::route::{IntoResponse, Response};
+
+/// The size of each chunk read from an `AsyncRead` source when streaming a response.
+const READER_CHUNK_SIZE: usize = 16 * 1024;
+
+pub type BodyStream = Pin<Box<dyn Send + Stream<Item = std::io::Result<Vec<u8>>>>>;
+
+/// The body of a [Response]. Bodies are either fully buffered in memory, and sent with a `Content-Length`,
+/// or streamed to the client as they are produced using `Transfer-Encoding: chunked` (or, for HTTP/1.0
+/// clients, until the connection is closed).
+pub enum ResponseBody {
+    Bytes(Vec<u8>),
+    Stream(BodyStream),
+}
+
+impl Default for ResponseBody {
+    fn default() -> Self {
+        Self::Bytes(Vec::new())
+    }
+}
+
+impl std::fmt::Debug for ResponseBody {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            ResponseBody::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            ResponseBody::Stream(_) => f.debug_tuple("Stream").finish(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for ResponseBody {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Bytes(bytes)
+    }
+}
+
+impl ResponseBody {
+    /// Writes the body to the client, chunk-encoding it if it's a stream and `chunked` is set. Ref: RFC 9112 7.1
+    pub(crate) async fn write_to<W: AsyncWrite + Unpin>(
+        self,
+        writer: &mut W,
+        chunked: bool,
+    ) -> std::io::Result<()> {
+        match self {
+            ResponseBody::Bytes(bytes) => writer.write_all(&bytes).await,
+            ResponseBody::Stream(mut stream) if !chunked => {
+                while let Some(chunk) = stream.next().await {
+                    writer.write_all(&chunk?).await?;
+                    writer.flush().await?;
+                }
+                Ok(())
+            },
+            ResponseBody::Stream(mut stream) => {
+                while let Some(chunk) = stream.next().await {
+                    let chunk = chunk?;
+                    if chunk.is_empty() {
+                        // A zero-length chunk would terminate the body early.
+                        continue;
+                    }
+                    writer.write_all(format!("{:X}\r\n", chunk.len()).as_bytes()).await?;
+                    writer.write_all(&chunk).await?;
+                    writer.write_all(b"\r\n").await?;
+                    writer.flush().await?;
+                }
+                writer.write_all(b"0\r\n\r\n").await
+            },
+        }
+    }
+}
+
+/// A response body that is streamed to the client as it is produced, instead of being buffered in memory.
+/// Useful for large exports or files.
+///
+/// ```ignore
+/// async fn export(events: PostgresDataProvider<Event>) -> StreamingBody {
+///     let file = tokio::fs::File::open("export.csv").await.unwrap();
+///     StreamingBody::from_reader(file)
+/// }
+/// ```
+pub struct StreamingBody {
+    stream: BodyStream,
+    content_type: String,
+}
+
+impl StreamingBody {
+    pub fn from_stream<S, B>(stream: S) -> Self
+    where
+        S: Stream<Item = std::io::Result<B>> + Send + 'static,
+        B: Into<Vec<u8>>,
+    {
+        Self {
+            stream: Box::pin(stream.map(|chunk| chunk.map(Into::into))),
+            content_type: "application/octet-stream".into(),
+        }
+    }
+
+    pub fn from_reader<R: AsyncRead + Send + 'static>(reader: R) -> Self {
+        let stream = futures::stream::unfold(Some(Box::pin(reader)), |reader| async move {
+            let mut reader = reader?;
+            let mut buf = vec![0; READER_CHUNK_SIZE];
+            match reader.read(&mut buf).await {
+                Ok(0) => None,
+                Ok(len) => {
+                    buf.truncate(len);
+                    Some((Ok(buf), Some(reader)))
+                },
+                // Stop reading after the first error.
+                Err(err) => Some((Err(err), None)),
+            }
+        });
+        Self::from_stream(stream)
+    }
+
+    pub fn with_content_type(
+        mut self,
+        content_type: impl Into<String>,
+    ) -> Self {
+        self.content_type = content_type.into();
+        self
+    }
+}
+
+impl IntoResponse for StreamingBody {
+    fn into_response(self) -> Response {
+        Response::ok().with_body_stream(self.stream).with_header("content-type", self.content_type)
+    }
+}
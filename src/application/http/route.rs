@@ -1,13 +1,9 @@
 use regex::Regex;
-use serde::{Deserialize, Serialize};
-use std::{
-    collections::HashMap,
-    fmt::Display,
-    io::{BufRead, Read},
-    ops::Deref,
-    pin::Pin,
-    sync::Arc,
+use serde::{
+    de::{value::StrDeserializer, DeserializeOwned},
+    Deserialize, Serialize,
 };
+use std::{collections::HashMap, fmt::Display, ops::Deref, pin::Pin, sync::Arc};
 use tailwag_macros::{Deref, Display};
 use tailwag_orm::{
     data_definition::exp_data_system::DataSystem,
@@ -17,10 +13,27 @@ use tailwag_orm::{
 use tailwag_utils::{
     data_strutures::hashmap_utils::GetOrDefault, types::generic_type_map::TypeInstanceMap,
 };
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 
 use crate::{
-    application::http::{headers::Headers, multipart::parse_multipart_request},
+    application::http::{
+        chunked::{read_chunked_body, ChunkedDecoder},
+        cookies::{Cookie, CookieHeader},
+        form::deserialize_urlencoded,
+        headers::Headers,
+        multipart::{
+            read_multipart_body, read_streamed_multipart, streamed_multipart_params,
+            MultipartLimits, MultipartParts,
+        },
+        negotiation::{deserialize_body, negotiate, serialize_response, Serializers},
+        path_params::PathParams,
+        query::{parse_query_params, split_request_target, QueryString},
+        response_body::{BodyStream, ResponseBody},
+        route_error::RouteError,
+    },
+    application::middleware::compression::is_content_encoded,
     auth::gateway::Session,
+    errors::deserialize_tracked,
 };
 use crate::{
     application::{http::into_route_handler::IntoRouteHandler, ConfigConstants},
@@ -38,6 +51,16 @@ pub enum RoutePolicy {
     #[allow(unused)]
     RequireAuthentication,
     RequireRole(String),
+    /// Applies the wrapped policy, but answers with a 404 instead of a 401 / 403 when it fails.
+    /// Use this for sensitive routes, where even revealing that the route exists is too much.
+    Hidden(Box<RoutePolicy>),
+}
+
+impl RoutePolicy {
+    /// Masks authorization failures for this policy as 404s. See [RoutePolicy::Hidden].
+    pub fn hidden(self) -> Self {
+        RoutePolicy::Hidden(Box::new(self))
+    }
 }
 
 impl Display for RoutePolicy {
@@ -49,6 +72,7 @@ impl Display for RoutePolicy {
             RoutePolicy::Public => write!(f, "PUBLIC"),
             RoutePolicy::RequireAuthentication => write!(f, "AUTHENTICATED"),
             RoutePolicy::RequireRole(role) => write!(f, "ROLE: {role}"),
+            RoutePolicy::Hidden(policy) => write!(f, "HIDDEN {policy}"),
         }
     }
 }
@@ -77,6 +101,9 @@ struct PoliciedRouteHandler {
     #[deref]
     handler: Box<RouteHandler>,
     _policy: RoutePolicy,
+    /// The names of the path parameters along this handler's path, in order.
+    /// Kept per handler, so that routes sharing a node can name their parameters differently.
+    param_names: Vec<String>,
 }
 
 // I'll probably end up ditching this for something... better.
@@ -87,6 +114,7 @@ impl PoliciedRouteHandler {
         Self {
             handler: Box::new(handler),
             _policy: RoutePolicy::Public,
+            param_names: Vec::new(),
         }
     }
     #[allow(unused)]
@@ -94,6 +122,7 @@ impl PoliciedRouteHandler {
         Self {
             handler: Box::new(handler),
             _policy: RoutePolicy::RequireAuthentication,
+            param_names: Vec::new(),
         }
     }
 }
@@ -103,7 +132,9 @@ impl PoliciedRouteHandler {
 pub struct Route {
     handlers: HashMap<HttpMethod, PoliciedRouteHandler>,
     children: HashMap<RoutePath, Route>,
-    dynamic_child: Option<(String, Box<Route>)>, // String = the given name. Only one supported for now, and it isn't actually extracted properly.
+    dynamic_child: Option<(String, Box<Route>)>, // String = the first name given, for printing. Handlers keep their own parameter names.
+    wildcard_child: Option<(String, Box<Route>)>, // Matches the rest of the path, e.g. `/static/{*path}`
+    errors: Vec<RouteError>,
 }
 impl std::fmt::Debug for Route {
     fn fmt(
@@ -128,7 +159,7 @@ impl Route {
             let padding = " ".repeat(8 - method.len());
 
             let policy = format!("{}", &handler._policy);
-            let policy_padding = " ".repeat(20 - policy.len());
+            let policy_padding = " ".repeat(20usize.saturating_sub(policy.len()));
             log::info!("   {policy}{policy_padding}{method}{padding}{prefix}/");
         }
         for (path, route) in &self.children {
@@ -139,6 +170,10 @@ impl Route {
             let new_prefix = format!("{prefix}/{{{dyn_string}}}");
             route.print_routes(&new_prefix);
         }
+        if let Some((wildcard_name, route)) = &self.wildcard_child {
+            let new_prefix = format!("{prefix}/{{*{wildcard_name}}}");
+            route.print_routes(&new_prefix);
+        }
     }
     pub fn print_all_routes(&self) {
         log::info!("\n\n");
@@ -157,69 +192,179 @@ impl Route {
     pub async fn handle(
         &self,
         mut request: Request,
-        context: RequestContext,
+        mut context: RequestContext,
     ) -> Response {
-        let path = &request.path;
-        let mut route = self;
+        context.insert_request_data(QueryString(request.query.clone()));
+        context.insert_request_data(CookieHeader(
+            request.headers.get_all("cookie").map(|cookie| cookie.to_string()).collect(),
+        ));
+        let mut segments = Vec::new();
+        for segment in request.path.split('/').filter(|s| !s.is_empty()) {
+            match urlencoding::decode(segment) {
+                Ok(s) => segments.push(s.into_owned()),
+                Err(_e) => return Response::bad_request(),
+            }
+        }
+        let mut param_values = Vec::new();
+        let Some(route) = self.find_route(&segments, &mut param_values) else {
+            return Default::default();
+        };
+
+        // HEAD is answered by the GET handler, unless one is given explicitly. The body is dropped when
+        // the response is written, so the headers still match what GET would send. Ref: RFC 9110 9.3.2
+        let handler = match (&request.method, route.handlers.get(&request.method)) {
+            (HttpMethod::Head, None) => route.handlers.get(&HttpMethod::Get),
+            (_, handler) => handler,
+        };
 
-        for segment in path.split('/').filter(|s| !s.is_empty()) {
-            match route.children.get(&segment.to_string()) {
-                Some(new_route) => route = new_route,
-                None => {
-                    if let Some((_name, new_route)) = &route.dynamic_child {
-                        let decoded = match urlencoding::decode(segment) {
-                            Ok(s) => s.into_owned(),
-                            Err(_e) => return Response::bad_request(),
-                        };
-                        request.path_params.push(decoded);
-                        route = new_route
-                    } else {
-                        return Default::default();
+        if let Some(future) = handler {
+            request.path_params = future.param_names.iter().cloned().zip(param_values).collect();
+            match is_authorized(&future._policy, &context).await {
+                // Only the compression middleware decodes bodies, and it removes the header when it does.
+                // Without it, tell the client to send the body as-is. Ref: RFC 9110 15.5.16
+                Authorization::Granted if is_content_encoded(&request.headers) => {
+                    Response::unsupported_media_type().with_header("Accept-Encoding", "identity")
+                },
+                Authorization::Granted => {
+                    let serializers =
+                        context.server_data.get::<Serializers>().cloned().unwrap_or_default();
+                    let accept = request.headers.get("accept").map(|accept| accept.to_string());
+                    negotiate(serializers, accept.as_deref(), future.call(request, context)).await
+                },
+                // Any other outcome, even a server error, would reveal that the route exists.
+                other if matches!(future._policy, RoutePolicy::Hidden(_)) => {
+                    if let Authorization::Error(err) = other {
+                        log::error!("[HIDDEN ROUTE] Authorization failed: {:?}", err);
                     }
+                    Response::not_found()
+                },
+                Authorization::Error(err) => err.into_response(),
+                // The client needs to log in (again) to use this route. Ref: RFC 9110 15.5.2
+                Authorization::Unauthenticated => {
+                    Response::unauthorized().with_header("WWW-Authenticate", "Bearer")
                 },
+                Authorization::Forbidden => Response::forbidden(),
             }
-        }
-
-        if let Some(future) = route.handlers.get(&request.method) {
-            //TODO: Verify policy
-            if is_authorized(&future._policy, &context).await {
-                future.call(request, context).await
+        } else if let Some(allowed_methods) = route.allowed_methods() {
+            if request.method == HttpMethod::Options {
+                Response::ok().with_header("Allow", allowed_methods)
             } else {
-                Response::default()
+                // The path exists, just not for this method. Ref: RFC 9110 15.5.6
+                Response::method_not_allowed().with_header("Allow", allowed_methods)
             }
         } else {
+            // Only hidden handlers live here, so as far as the client can tell, nothing does.
             Response::default()
         }
     }
+
+    /// Finds the node that handles the given path segments, collecting the path parameters along the way.
+    /// Static children take precedence over dynamic ones, which take precedence over wildcards. When a more
+    /// specific branch turns out to be a dead end, the next one is tried.
+    fn find_route<'a>(
+        &'a self,
+        segments: &[String],
+        params: &mut Vec<String>,
+    ) -> Option<&'a Route> {
+        let Some((segment, rest)) = segments.split_first() else {
+            return (!self.handlers.is_empty()).then_some(self);
+        };
+        if let Some(route) =
+            self.children.get(segment).and_then(|child| child.find_route(rest, params))
+        {
+            return Some(route);
+        }
+        if let Some((_name, child)) = &self.dynamic_child {
+            params.push(segment.clone());
+            if let Some(route) = child.find_route(rest, params) {
+                return Some(route);
+            }
+            params.pop();
+        }
+        match &self.wildcard_child {
+            Some((_name, child)) if !child.handlers.is_empty() => {
+                params.push(segments.join("/"));
+                Some(child)
+            },
+            _ => None,
+        }
+    }
+
+    /// The methods this route answers to, formatted for an `Allow` header.
+    /// Includes the methods that are handled automatically, i.e. HEAD (for GET routes) and OPTIONS.
+    /// Hidden handlers are left out, so that they can't be discovered this way - `None` if that's all of them.
+    fn allowed_methods(&self) -> Option<String> {
+        let visible: Vec<&HttpMethod> = self
+            .handlers
+            .iter()
+            .filter(|(_, handler)| !matches!(handler._policy, RoutePolicy::Hidden(_)))
+            .map(|(method, _)| method)
+            .collect();
+        if visible.is_empty() {
+            return None;
+        }
+        let mut methods: Vec<String> =
+            visible.iter().map(|method| method.to_string().to_uppercase()).collect();
+        if visible.contains(&&HttpMethod::Get) {
+            methods.push("HEAD".into());
+        }
+        methods.push("OPTIONS".into());
+        methods.sort();
+        methods.dedup();
+        Some(methods.join(", "))
+    }
+}
+
+/// The outcome of checking a request against a [RoutePolicy].
+enum Authorization {
+    Granted,
+    /// No (valid) session was provided with the request.
+    Unauthenticated,
+    /// The session is valid, but doesn't grant access to the route.
+    Forbidden,
+    /// The policy couldn't be checked, e.g. because the server is missing a data provider.
+    Error(crate::Error),
 }
 
 async fn is_authorized(
     policy: &RoutePolicy,
     ctx: &RequestContext,
-) -> bool {
+) -> Authorization {
+    // Hidden only changes how failures are reported, so the policy it wraps is checked instead.
+    let mut policy = policy;
+    while let RoutePolicy::Hidden(inner) = policy {
+        policy = inner.as_ref();
+    }
+    let session = ctx.get_request_data::<Session>();
     match policy {
-        RoutePolicy::Public => true, // Always allow public routes
-        RoutePolicy::RequireAuthentication => {
-            ctx.get_request_data::<Session>().map_or(false, |_session| {
-                // TODO: For now we just look to see if the session exists. Need to add better validation.
-                // THIS IS NOT WELL-TESTED
-                true
-            })
+        RoutePolicy::Public => Authorization::Granted, // Always allow public routes
+        // TODO: For now we just look to see if the session exists. Need to add better validation.
+        // THIS IS NOT WELL-TESTED
+        RoutePolicy::RequireAuthentication => match session {
+            Some(_session) => Authorization::Granted,
+            None => Authorization::Unauthenticated,
         },
         RoutePolicy::RequireRole(role) => {
             // TODO: THis needs to be re-worked when Roles are addressed. For now, this only works for the
             // `Admin` role, and uses a hardcoded boolean flag instead of actual roles.
-            let Some(users) = ctx.get::<AppUser>() else {
-                return false;
-            };
-            let session = ctx.get_request_data::<Session>(); //map_or(false, |sess| todo!())
             let Some(user_id) = session.map(|sess| sess.account_id) else {
-                return false;
+                return Authorization::Unauthenticated;
+            };
+            let Some(users) = ctx.get::<AppUser>() else {
+                return Authorization::Error(crate::Error::InternalServerError(
+                    "No AppUser data provider is configured to check roles with.".into(),
+                ));
             };
             let user = users.get(|u| u.id.eq(user_id)).await.ok().flatten();
             // Hacked together - if "admin" is the requested role, and the user is an admin, then and only then will this succeed.
-            user.map_or(false, |u| u.is_admin()) && role.to_lowercase() == "admin"
+            if user.is_some_and(|u| u.is_admin()) && role.to_lowercase() == "admin" {
+                Authorization::Granted
+            } else {
+                Authorization::Forbidden
+            }
         },
+        // Already unwrapped above. Denied rather than trusted, should that ever change.
+        RoutePolicy::Hidden(_) => Authorization::Forbidden,
     }
 }
 
@@ -272,18 +417,22 @@ impl Route {
     impl_method!(post:Post);
     impl_method!(delete:Delete);
     impl_method!(patch:Patch);
+    impl_method!(put:Put);
     impl_method!(get_with_policy:Get, with_policy);
     impl_method!(post_with_policy:Post, with_policy);
     impl_method!(delete_with_policy:Delete, with_policy);
     impl_method!(patch_with_policy:Patch, with_policy);
+    impl_method!(put_with_policy:Put, with_policy);
     impl_method!(get_protected:Get, protected);
     impl_method!(post_protected:Post, protected);
     impl_method!(delete_protected:Delete, protected);
     impl_method!(patch_protected:Patch, protected);
+    impl_method!(put_protected:Put, protected);
     impl_method!(get_public:Get, public);
     impl_method!(post_public:Post, public);
     impl_method!(delete_public:Delete, public);
     impl_method!(patch_public:Patch, public);
+    impl_method!(put_public:Put, public);
 }
 
 #[allow(unused)]
@@ -303,6 +452,21 @@ impl Route {
         self.add_handler(method, path, handler, policy);
         self
     }
+
+    /// Like [Route::with_handler], but returns invalid or conflicting routes as an error.
+    pub fn try_with_handler<F, I, O>(
+        mut self,
+        method: HttpMethod,
+        path: &str,
+        handler: impl IntoRouteHandler<F, I, O>,
+        policy: RoutePolicy,
+    ) -> Result<Self, RouteError> {
+        self.try_add_handler(method, path, handler, policy)?;
+        Ok(self)
+    }
+
+    /// Adds a handler to the route tree. If the route is invalid or conflicts with an existing one,
+    /// the error is kept in [Route::errors] and reported when the service is built.
     pub fn add_handler<F, I, O>(
         &mut self,
         method: HttpMethod,
@@ -310,47 +474,123 @@ impl Route {
         handler: impl IntoRouteHandler<F, I, O>,
         policy: RoutePolicy,
     ) {
-        let parts = path.split('/');
+        if let Err(err) = self.try_add_handler(method, path, handler, policy) {
+            log::error!("{}", &err);
+            self.errors.push(err);
+        }
+    }
+
+    pub fn try_add_handler<F, I, O>(
+        &mut self,
+        method: HttpMethod,
+        path: &str,
+        handler: impl IntoRouteHandler<F, I, O>,
+        policy: RoutePolicy,
+    ) -> Result<(), RouteError> {
+        let segments = RouteSegment::parse_path(path, true)?;
+        let (route, param_names) = self.node_for_path(segments);
+        if route.handlers.contains_key(&method) {
+            return Err(RouteError::DuplicateHandler {
+                method: method.to_string().to_uppercase(),
+                path: path.to_string(),
+            });
+        }
+
+        route.handlers.insert(
+            method,
+            PoliciedRouteHandler {
+                handler: Box::new(handler.into()),
+                _policy: policy,
+                param_names,
+            },
+        );
+        Ok(())
+    }
+
+    /// Any invalid or conflicting routes that were added through the infallible builders.
+    pub fn errors(&self) -> &[RouteError] {
+        &self.errors
+    }
+
+    /// Walks down the route tree along the (already validated) segments, creating any missing nodes, and
+    /// returns the node at the end along with the names of the path parameters on the way there.
+    fn node_for_path(
+        &mut self,
+        segments: Vec<RouteSegment>,
+    ) -> (&mut Route, Vec<String>) {
         let mut route = self;
-        for part in parts.filter(|p| !p.is_empty()) {
-            if let Some(captures) = Regex::new("^\\{(?<name>[a-zA-Z0-9_-]*)\\}$") // /route/{this_part_gets_matched}/
-                .expect("Something wrong with regex")
-                .captures(part)
+        let mut param_names: Vec<String> = Vec::new();
+        for segment in segments {
+            route = match segment {
+                RouteSegment::Static(name) => route.children.get_or_default_mut(&name),
+                // Only one dynamic child is allowed per node, which keeps matching unambiguous.
+                // The parameter names are tracked per handler, so `/event/{id}` and `/event/{event_id}/tags` can coexist.
+                RouteSegment::Dynamic(name) => {
+                    param_names.push(name.clone());
+                    &mut *route.dynamic_child.get_or_insert_with(|| (name, Box::default())).1
+                },
+                RouteSegment::Wildcard(name) => {
+                    param_names.push(name.clone());
+                    &mut *route.wildcard_child.get_or_insert_with(|| (name, Box::default())).1
+                },
+            };
+        }
+        (route, param_names)
+    }
+
+    /// Merges `other` into this node. Handlers in `other` get the parameter names of the path they
+    /// were mounted at prepended to their own. Conflicting handlers are skipped and reported in `errors`.
+    fn merge(
+        &mut self,
+        other: Route,
+        prefix_params: &[String],
+        mount_path: &str,
+        errors: &mut Vec<RouteError>,
+    ) {
+        let Route {
+            handlers,
+            children,
+            dynamic_child,
+            wildcard_child,
+            errors: _,
+        } = other;
+        for (method, mut handler) in handlers {
+            if let Some(name) = handler.param_names.iter().find(|name| prefix_params.contains(name))
             {
-                // For now, only one dynamic route is allowed per route.
-                // Reduces ambiguity (and lets me get away with this silly hack)
-                // In the future, I'll add some regex support (maybe?) or at least a basic extraction syntax
-                if route.dynamic_child.is_none() {
-                    route.dynamic_child = Some((captures["name"].to_string(), Box::default()));
-                }
-                let (name, child_route) =
-                    route.dynamic_child.as_mut().expect("Missing route that was just added.");
-                route = &mut *child_route;
-            } else if Regex::new("^[a-zA-Z0-9_]+$").expect("Regex is invalid").is_match(part) {
-                route = route.children.get_or_default_mut(&part.to_string());
-            } else if part.matches("...").next().is_some() {
-                // TODO:
-                todo!("... = \"the rest of the input\"");
-            } else {
-                log::error!("part: {} doesn't match regex", &part);
-                panic!("Invalid route");
+                errors.push(RouteError::AmbiguousParameter {
+                    name: name.clone(),
+                    path: mount_path.to_string(),
+                });
+                continue;
+            }
+            if self.handlers.contains_key(&method) {
+                errors.push(RouteError::DuplicateHandler {
+                    method: method.to_string().to_uppercase(),
+                    path: mount_path.to_string(),
+                });
+                continue;
             }
+            handler.param_names.splice(0..0, prefix_params.iter().cloned());
+            self.handlers.insert(method, handler);
         }
-
-        // TODO: NEed to indicate that it's extracting something.
-        // Static vs Dynamic routes
-        if route
-            .handlers
-            .insert(
-                method,
-                PoliciedRouteHandler {
-                    handler: Box::new(handler.into()),
-                    _policy: policy,
-                },
-            )
-            .is_some()
-        {
-            panic!("This route ({}) already has a handler", &path);
+        for (path, child) in children {
+            self.children.get_or_default_mut(&path).merge(child, prefix_params, mount_path, errors);
+        }
+        if let Some((name, child)) = dynamic_child {
+            self.dynamic_child.get_or_insert_with(|| (name, Box::default())).1.merge(
+                *child,
+                prefix_params,
+                mount_path,
+                errors,
+            );
+        }
+        if let Some((name, child)) = wildcard_child {
+            self.wildcard_child.get_or_insert_with(|| (name, Box::default())).1.merge(
+                *child,
+                prefix_params,
+                mount_path,
+                errors,
+            );
         }
     }
 
@@ -363,27 +603,128 @@ impl Route {
         self
     }
 
-    /// Nest another Route inside this one.
-    /// ```
+    /// Nest another Route inside this one. The path is parsed the same way as handler paths, so it can
+    /// span several segments and include path parameters, e.g. `{tenant}/events`. If part of the path
+    /// already exists, the nested route is merged into it.
+    ///
+    /// Any errors (including those already collected by the nested route) are kept in [Route::errors].
     pub fn route(
         &mut self,
         path: impl Into<RoutePath>,
         route: Route,
     ) {
-        // 1. Parse path (as str) into the parts
-        // 2. Go down the request tree to find the Route node where this route would be handled, creating new Route nodes when necessary.
-        // 3. Add the Route handler
-        self.children.insert(path.into(), route);
+        if let Err(errors) = self.try_route(path, route) {
+            for err in &errors {
+                log::error!("{}", err);
+            }
+            self.errors.extend(errors);
+        }
+    }
+
+    /// Like [Route::route], but returns the errors instead of collecting them. Handlers that don't
+    /// conflict are still added.
+    pub fn try_route(
+        &mut self,
+        path: impl Into<RoutePath>,
+        mut route: Route,
+    ) -> Result<(), Vec<RouteError>> {
+        let path = path.into();
+        let segments = RouteSegment::parse_path(&path, false).map_err(|err| vec![err])?;
+        let mut errors = std::mem::take(&mut route.errors);
+        let (node, prefix_params) = self.node_for_path(segments);
+        node.merge(route, &prefix_params, &path, &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// A single segment of a route definition.
+enum RouteSegment {
+    /// Matches the segment exactly, e.g. `events`.
+    Static(String),
+    /// Matches any single segment, e.g. `{event_id}`.
+    Dynamic(String),
+    /// Matches the rest of the path, e.g. `{*path}`.
+    Wildcard(String),
+}
+
+impl RouteSegment {
+    /// Parses and validates every segment of a route path.
+    /// Wildcards are only allowed as the last segment of a handler path, never in a nested route's mount path.
+    fn parse_path(
+        path: &str,
+        allow_wildcard: bool,
+    ) -> Result<Vec<Self>, RouteError> {
+        let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+        let mut param_names: Vec<String> = Vec::new();
+        let mut segments = Vec::new();
+        for (i, part) in parts.iter().enumerate() {
+            let segment = Self::parse(part).ok_or_else(|| RouteError::InvalidSegment {
+                segment: part.to_string(),
+                path: path.to_string(),
+            })?;
+            match &segment {
+                RouteSegment::Wildcard(_) if !allow_wildcard || i + 1 < parts.len() => {
+                    Err(RouteError::MisplacedWildcard {
+                        path: path.to_string(),
+                    })?
+                },
+                RouteSegment::Dynamic(name) | RouteSegment::Wildcard(name) => {
+                    if param_names.contains(name) {
+                        Err(RouteError::AmbiguousParameter {
+                            name: name.clone(),
+                            path: path.to_string(),
+                        })?
+                    }
+                    param_names.push(name.clone());
+                },
+                RouteSegment::Static(_) => (),
+            }
+            segments.push(segment);
+        }
+        Ok(segments)
+    }
+
+    fn parse(part: &str) -> Option<Self> {
+        if let Some(captures) = Regex::new("^\\{\\*(?<name>[a-zA-Z0-9_-]+)\\}$") // /route/{*the/rest/of/the/path}
+            .expect("Something wrong with regex")
+            .captures(part)
+        {
+            Some(RouteSegment::Wildcard(captures["name"].to_string()))
+        } else if let Some(captures) = Regex::new("^\\{(?<name>[a-zA-Z0-9_-]*)\\}$") // /route/{this_part_gets_matched}/
+            .expect("Something wrong with regex")
+            .captures(part)
+        {
+            Some(RouteSegment::Dynamic(captures["name"].to_string()))
+        } else if part == "." || part == ".." {
+            // Dot-segments are removed from request paths, so they could never be matched. Ref: RFC 3986 5.2.4
+            None
+        } else if Regex::new("^(?:[A-Za-z0-9\\-._~!$&'()*+,;=:@]|%[0-9A-Fa-f]{2})+$") // RFC 3986 pchar
+            .expect("Regex is invalid")
+            .is_match(part)
+        {
+            // Requests are matched against decoded segments, so percent-encoded routes are stored decoded too.
+            urlencoding::decode(part).ok().map(|part| RouteSegment::Static(part.into_owned()))
+        } else {
+            None
+        }
     }
 }
 
 #[derive(Eq, PartialEq, Hash, Debug, Display)]
 pub enum HttpMethod {
     Get,
+    Head,
     Post,
+    Put,
     Delete,
     Patch,
     Options,
+    Trace,
+    Connect,
 }
 
 impl TryFrom<&str> for HttpMethod {
@@ -393,10 +734,14 @@ impl TryFrom<&str> for HttpMethod {
         type E = HttpMethod;
         let method = match value.to_uppercase().as_str() {
             "GET" => E::Get,
+            "HEAD" => E::Head,
             "POST" => E::Post,
+            "PUT" => E::Put,
             "PATCH" => E::Patch,
             "DELETE" => E::Delete,
             "OPTIONS" => E::Options,
+            "TRACE" => E::Trace,
+            "CONNECT" => E::Connect,
             _ => Err(format!("Unsupported HTTP method: {}", value))?,
         };
         Ok(method)
@@ -414,12 +759,15 @@ pub enum HttpStatus {
     Unauthorized = 401,
     Forbidden = 403,
     NotFound = 404,
+    MethodNotAllowed = 405,
+    NotAcceptable = 406,
     Conflict = 409,
     EntityTooLarge = 413,
     UnsupportedMediaType = 415,
     IAmATeapot = 418,
+    InternalServerError = 500,
     NotImplemented = 501,
-    InternalServerError = 503,
+    ServiceUnavailable = 503,
 }
 
 impl Display for HttpStatus {
@@ -427,26 +775,30 @@ impl Display for HttpStatus {
         &self,
         f: &mut std::fmt::Formatter<'_>,
     ) -> std::fmt::Result {
-        let var_name = format!(
-            "{} {}",
-            self.clone() as usize,
-            match self {
-                HttpStatus::Ok => "OK",
-                HttpStatus::Accepted => "Accepted",
-                HttpStatus::SeeOther => "See Other",
-                HttpStatus::BadRequest => "Bad Request",
-                HttpStatus::Unauthorized => "Unauthorized",
-                HttpStatus::Forbidden => "Forbidden",
-                HttpStatus::NotFound => "Not Found",
-                HttpStatus::NotImplemented => "Not Implemented",
-                HttpStatus::IAmATeapot => "I Am A Teapot",
-                HttpStatus::InternalServerError => "Internal Server Error",
-                HttpStatus::Conflict => "Conflict",
-                HttpStatus::EntityTooLarge => "Entity Too Large",
-                HttpStatus::UnsupportedMediaType => "Unsupported Media Type",
-            }
-        );
-        f.write_str(&var_name)
+        write!(f, "{} {}", self.clone() as usize, self.reason_phrase())
+    }
+}
+
+impl HttpStatus {
+    pub fn reason_phrase(&self) -> &'static str {
+        match self {
+            HttpStatus::Ok => "OK",
+            HttpStatus::Accepted => "Accepted",
+            HttpStatus::SeeOther => "See Other",
+            HttpStatus::BadRequest => "Bad Request",
+            HttpStatus::Unauthorized => "Unauthorized",
+            HttpStatus::Forbidden => "Forbidden",
+            HttpStatus::NotFound => "Not Found",
+            HttpStatus::MethodNotAllowed => "Method Not Allowed",
+            HttpStatus::NotAcceptable => "Not Acceptable",
+            HttpStatus::NotImplemented => "Not Implemented",
+            HttpStatus::IAmATeapot => "I Am A Teapot",
+            HttpStatus::InternalServerError => "Internal Server Error",
+            HttpStatus::ServiceUnavailable => "Service Unavailable",
+            HttpStatus::Conflict => "Conflict",
+            HttpStatus::EntityTooLarge => "Entity Too Large",
+            HttpStatus::UnsupportedMediaType => "Unsupported Media Type",
+        }
     }
 }
 
@@ -472,8 +824,9 @@ impl RouteHandler {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum HttpVersion {
+    V1_0,
     V1_1,
     V2_0,
     V3_0,
@@ -485,6 +838,7 @@ impl TryFrom<&str> for HttpVersion {
         type E = HttpVersion;
 
         Ok(match value {
+            "HTTP/1.0" => E::V1_0,
             "HTTP/1.1" => E::V1_1,
             "HTTP/2.0" => E::V2_0,
             "HTTP/3.0" => E::V3_0,
@@ -497,6 +851,7 @@ impl Deref for HttpVersion {
 
     fn deref(&self) -> &Self::Target {
         match self {
+            HttpVersion::V1_0 => "HTTP/1.0",
             HttpVersion::V1_1 => "HTTP/1.1",
             HttpVersion::V2_0 => "HTTP/2.0",
             HttpVersion::V3_0 => "HTTP/3.0",
@@ -510,101 +865,244 @@ pub struct Request {
     // TODO: This could be a u8 in the future, sinc eit won't always be text.
     pub method: HttpMethod,
     pub path: String,
-    pub path_params: Vec<String>,
+    pub path_params: PathParams,
+    /// The raw query string, without the leading `?`.
+    pub query: String,
+    pub query_params: HashMap<String, String>,
     pub http_version: HttpVersion,
     pub headers: Headers,
     pub body: HttpBody,
 }
 
+fn deserialize_json<T: DeserializeOwned>(body: &str) -> Result<T, crate::Error> {
+    let mut deserializer = serde_json::Deserializer::from_str(body);
+    let desered = deserialize_tracked(&mut deserializer, "Invalid JSON body")?;
+    deserializer.end()?;
+    Ok(desered)
+}
+
 impl<T: for<'a> Deserialize<'a>> FromRequest for T {
     fn from(req: Request) -> Result<Self, crate::Error> {
         // TODO: Return this as a Result so we can route based on it later
         // ^^^ that didn't work,
         match &req.body {
-            HttpBody::Json(body) => {
-                let bytes = body.as_bytes();
-                let desered = serde_json::from_slice(bytes)?;
-                Ok(desered)
-                // serde_json::from_slice(body.as_bytes()).unwrap(),
+            HttpBody::Json(body) => deserialize_json(body),
+            HttpBody::Form(body) => deserialize_urlencoded(body, "Invalid form body"),
+            HttpBody::Bytes(bytes) => {
+                let Some(media_type) = req.content_type() else {
+                    // Typed bodies sent without a `Content-Type` have always been read as JSON.
+                    return deserialize_json(std::str::from_utf8(bytes)?);
+                };
+                // Any other registered representation, e.g. CBOR or YAML.
+                if let Some(result) = deserialize_body(&media_type, bytes) {
+                    return result;
+                }
+                // Anything else can still be extracted as a string (or something that deserializes from one).
+                let text =
+                    std::str::from_utf8(bytes).map_err(crate::Error::from).and_then(|text| {
+                        Ok(T::deserialize(StrDeserializer::<serde::de::value::Error>::new(text))?)
+                    });
+                match text {
+                    Ok(value) => Ok(value),
+                    // Only text is expected to read as one, so any other media type just isn't supported.
+                    Err(err) if media_type.starts_with("text/") => Err(err),
+                    Err(_) => crate::Error::unsupported_media_type(),
+                }
             },
-            // HttpBody::Bytes(_) => todo!(),
-            // HttpBody::Stream(_) => todo!(),
             // HttpBody::Multipart(_) => todo!(),
-            // // HttpBody::Plaintext(String) => todo!(),
             // HttpBody::None => todo!(),
-            // HttpBody::Html(_) => todo!(),
-            _ => Err("Unsupported content-type provided - the way content-type is handled needs to be rethought.")?
+            _ => crate::Error::unsupported_media_type(),
         }
     }
 }
 
+/// The parsed request body. The media type it was parsed from stays in the headers, see [Request::content_type].
 #[derive(Debug)]
-// TODO: This abstruction turned out to suck. Keep the content-type separate from the data.
 pub enum HttpBody {
-    // pub bytes: Vec<u8>,
     Json(String),
+    /// An `application/x-www-form-urlencoded` body, as sent by HTML forms. Still percent-encoded.
+    Form(String),
+    /// Any other media type, e.g. `text/plain`, `application/octet-stream`, or vendor types.
     Bytes(Vec<u8>),
-    Multipart(HashMap<String, MultipartPart>),
-    Stream(std::io::BufReader<std::net::TcpStream>),
-    Html(String),
+    Multipart(MultipartParts),
     None,
 }
 
-const DEFAULT_CONTENT_TYPE: &str = "application/json";
-
-impl TryFrom<&std::net::TcpStream> for Request {
-    fn try_from(stream: &std::net::TcpStream) -> Result<Self, Self::Error> {
-        let mut stream = std::io::BufReader::new(stream);
-        let stream = &mut stream;
-
-        let mut line = String::new();
-        stream.take(ConfigConstants::request_line_max_length()).read_line(&mut line)?;
+/// A body without a `Content-Type` is just bytes, as far as we can tell. Ref: RFC 9110 8.3
+/// Typed extractors still read such bodies as JSON, so older clients that leave it out keep working.
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
 
-        let mut routing_line = line.split_whitespace();
-        let (Some(method), Some(path), Some(http_version)) =
-            (routing_line.next(), routing_line.next(), routing_line.next())
-        else {
-            Err(Self::Error::BadRequest(format!("Invalid routing header found: {}", &line)))?
-        };
-        let headers = Headers::parse_headers(stream)?;
-        let content_length: usize =
-            headers.get("content-length").and_then(|c| c.parse().ok()).unwrap_or(0);
+impl HttpBody {
+    /// Parses the raw body bytes into the representation for the request's `Content-Type`.
+    pub async fn parse(
+        headers: &Headers,
+        bytes: Vec<u8>,
+    ) -> Result<Self, crate::Error> {
+        // Compressed bodies are kept as they are, until the compression middleware decodes them (or the
+        // route answers 415, without it).
+        if is_content_encoded(headers) {
+            return Ok(HttpBody::Bytes(bytes));
+        }
         let content_type_header =
             headers.get("content-type").map(|s| s.as_str()).unwrap_or(DEFAULT_CONTENT_TYPE);
-
         let (content_type, content_type_params) =
             content_type_header.split_once(';').unwrap_or((content_type_header, ""));
-        type E = HttpBody;
 
-        if content_length > ConfigConstants::max_content_length() as usize {
-            crate::Error::entity_too_large()?;
+        let body = match content_type.trim().to_lowercase().as_str() {
+            "application/json" => HttpBody::Json(String::from_utf8(bytes)?),
+            "multipart/form-data" => HttpBody::Multipart(
+                read_multipart_body(
+                    &mut bytes.as_slice(),
+                    content_type_params,
+                    &MultipartLimits::default(),
+                )
+                .await?,
+            ),
+            "application/x-www-form-urlencoded" => HttpBody::Form(String::from_utf8(bytes)?),
+            _ => HttpBody::Bytes(bytes),
+        };
+        Ok(body)
+    }
+
+    /// The body as raw bytes. Multipart bodies are split into their parts as they are read, so they
+    /// can't be turned back into bytes.
+    pub fn into_bytes(self) -> Result<Vec<u8>, crate::Error> {
+        match self {
+            HttpBody::Json(body) | HttpBody::Form(body) => Ok(body.into_bytes()),
+            HttpBody::Bytes(bytes) => Ok(bytes),
+            HttpBody::None => Ok(Vec::new()),
+            HttpBody::Multipart(_) => crate::Error::unsupported_media_type(),
         }
-        let body = if content_length > 0 {
-            let mut bytes = vec![0; content_length];
-            log::info!("Reading {} bytes", content_length);
-            stream.read_exact(&mut bytes)?;
-            match content_type.to_lowercase().as_str() {
-                "application/json" => {
-                    dbg!(E::Json(String::from_utf8(bytes)?))
-                },
-                "multipart/form-data" => parse_multipart_request(content_type_params, bytes)?,
-                _ => crate::Error::unsupported_media_type()?,
+    }
+}
+
+impl Request {
+    /// The media type of the body, lowercased and without parameters, e.g. `text/plain` for
+    /// `Content-Type: text/plain; charset=utf-8`.
+    pub fn content_type(&self) -> Option<String> {
+        self.headers
+            .get("content-type")
+            .and_then(|content_type| content_type.split(';').next())
+            .map(|media_type| media_type.trim().to_lowercase())
+    }
+
+    /// Whether the client wants the connection kept open after this request.
+    /// HTTP/1.1 connections are persistent unless the client sends `Connection: close`, while
+    /// HTTP/1.0 clients have to opt in with `Connection: keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.headers.get("connection").map(|c| c.to_lowercase());
+        let has_token = |token: &str| {
+            connection.as_ref().is_some_and(|c| c.split(',').any(|t| t.trim() == token))
+        };
+        match self.http_version {
+            HttpVersion::V1_0 => has_token("keep-alive"),
+            _ => !has_token("close"),
+        }
+    }
+
+    /// Reads the next request off of a (possibly persistent) connection.
+    ///
+    /// The same `BufReader` must be reused for every request on a connection, since it may already
+    /// hold bytes belonging to the next pipelined request.
+    /// Returns `Ok(None)` if the client closed the connection before sending another request.
+    pub async fn read_next<T: AsyncRead + Unpin>(
+        stream: &mut BufReader<T>
+    ) -> Result<Option<Self>, crate::Error> {
+        let mut line = String::new();
+        // RFC 9112 2.2: Servers SHOULD ignore at least one empty line received prior to the request-line.
+        while line.trim().is_empty() {
+            line.clear();
+            if stream.take(ConfigConstants::request_line_max_length()).read_line(&mut line).await?
+                == 0
+            {
+                return Ok(None);
             }
-        } else {
-            HttpBody::None
+        }
+        // A line cut off by the length limit would otherwise be parsed as if it were complete.
+        if !line.ends_with('\n') {
+            crate::Error::bad_request("Request line too long or incomplete.")?;
+        }
+
+        let mut routing_line = line.split_whitespace();
+        let (Some(method), Some(target), Some(http_version)) =
+            (routing_line.next(), routing_line.next(), routing_line.next())
+        else {
+            Err(crate::Error::BadRequest(format!("Invalid routing header found: {}", &line)))?
         };
+        let mut headers = Headers::parse_headers(stream).await?;
+        let body = Self::read_body(stream, &mut headers).await?;
 
-        Ok(Request {
+        let (path, query) = split_request_target(target);
+        Ok(Some(Request {
             method: method.try_into()?,
             path: path.to_string(), // TODO: Validate it
             path_params: Default::default(),
+            query: query.to_string(),
+            query_params: parse_query_params(query),
             http_version: http_version.try_into()?,
             headers,
             body,
-        })
+        }))
     }
 
-    type Error = crate::Error;
+    /// Reads the body off of the stream, as framed by the `Transfer-Encoding` or `Content-Length` headers.
+    ///
+    /// Multipart bodies are parsed straight off of the stream, with large parts spooled to disk, so they
+    /// get their own (larger) [MultipartLimits]. Either way, the whole body has to arrive within the
+    /// server's request timeout, so that should leave enough time for the largest expected upload.
+    async fn read_body<T: AsyncRead + Unpin>(
+        stream: &mut BufReader<T>,
+        headers: &mut Headers,
+    ) -> Result<HttpBody, crate::Error> {
+        let multipart_params = streamed_multipart_params(headers);
+
+        // Transfer-Encoding takes precedence over Content-Length. Ref: RFC 9112 6.3
+        if let Some(transfer_encoding) = headers.get("transfer-encoding") {
+            // Sending both is a request smuggling vector, so the request is rejected (which also closes
+            // the connection). Ref: RFC 9112 6.1
+            if headers.contains_key("content-length") {
+                crate::Error::bad_request("Content-Length is not allowed with Transfer-Encoding")?;
+            }
+            if !headers.is_chunked() {
+                crate::Error::bad_request(&format!(
+                    "Unsupported transfer-encoding: {}",
+                    transfer_encoding
+                ))?;
+            }
+            if let Some(params) = multipart_params {
+                let limits = MultipartLimits::default();
+                let body = ChunkedDecoder::new(stream, headers, limits.max_length).into_reader();
+                return read_streamed_multipart(body, &params, &limits).await;
+            }
+            let bytes = read_chunked_body(stream, headers).await?;
+            if bytes.is_empty() {
+                return Ok(HttpBody::None);
+            }
+            return HttpBody::parse(headers, bytes).await;
+        }
+
+        let content_length = headers.content_length()?.unwrap_or(0) as usize;
+        if content_length == 0 {
+            return Ok(HttpBody::None);
+        }
+
+        if let Some(params) = multipart_params {
+            let limits = MultipartLimits::default();
+            if content_length as u64 > limits.max_length {
+                crate::Error::entity_too_large()?;
+            }
+            let body = (&mut *stream).take(content_length as u64);
+            return read_streamed_multipart(body, &params, &limits).await;
+        }
+
+        if content_length > ConfigConstants::max_content_length() as usize {
+            crate::Error::entity_too_large()?;
+        }
+        let mut bytes = vec![0; content_length];
+        log::info!("Reading {} bytes", content_length);
+        stream.read_exact(&mut bytes).await?;
+        HttpBody::parse(headers, bytes).await
+    }
 }
 
 #[derive(Debug)]
@@ -612,7 +1110,7 @@ pub struct Response {
     pub http_version: HttpVersion,
     pub status: HttpStatus,
     pub headers: Headers,
-    pub body: Vec<u8>,
+    pub body: ResponseBody,
 }
 // impl<E: std::error::Error> From<E> for Response {
 //     fn from(value: E) -> Self {
@@ -622,8 +1120,7 @@ pub struct Response {
 
 impl From<tailwag_orm::Error> for Response {
     fn from(value: tailwag_orm::Error) -> Self {
-        log::error!("Request errored out: {:?}", &value);
-        Response::internal_server_error()
+        crate::Error::from(value).into_response()
     }
 }
 
@@ -635,7 +1132,7 @@ macro_rules! default_response {
                 http_version: HttpVersion::V1_1,
                 status: HttpStatus::$enumname,
                 headers: Headers::default(),
-                body: Vec::new(),
+                body: ResponseBody::default(),
             }
         }
     };
@@ -645,22 +1142,26 @@ macro_rules! default_response {
 impl Response {
     default_response!(bad_request, BadRequest);
     default_response!(not_found, NotFound);
+    default_response!(method_not_allowed, MethodNotAllowed);
     default_response!(not_implemented, NotImplemented);
     default_response!(internal_server_error, InternalServerError);
+    default_response!(service_unavailable, ServiceUnavailable);
     default_response!(entity_too_large, EntityTooLarge);
     default_response!(unauthorized, Unauthorized);
+    default_response!(forbidden, Forbidden);
     default_response!(unsupported_media_type, UnsupportedMediaType);
+    default_response!(not_acceptable, NotAcceptable);
     default_response!(conflict, Conflict);
     default_response!(ok, Ok);
     pub fn redirect_see_other(redirect_url: &str) -> Self {
         let mut headers = Headers::default();
-        headers.insert("Location".into(), redirect_url.into());
+        headers.insert("Location", redirect_url);
 
         Self {
             http_version: HttpVersion::V1_1,
             status: HttpStatus::SeeOther,
             headers,
-            body: Vec::new(),
+            body: ResponseBody::default(),
         }
     }
 }
@@ -672,11 +1173,26 @@ impl Default for Response {
 }
 
 impl Response {
+    pub fn with_status(
+        mut self,
+        status: HttpStatus,
+    ) -> Self {
+        self.status = status;
+        self
+    }
     pub fn with_body(
         mut self,
         bytes: Vec<u8>,
     ) -> Self {
-        self.body = bytes;
+        self.body = ResponseBody::Bytes(bytes);
+        self
+    }
+    /// Streams the body to the client with `Transfer-Encoding: chunked`, instead of buffering it in memory.
+    pub fn with_body_stream(
+        mut self,
+        stream: BodyStream,
+    ) -> Self {
+        self.body = ResponseBody::Stream(stream);
         self
     }
     pub fn with_header(
@@ -684,29 +1200,103 @@ impl Response {
         name: impl Into<String>,
         val: impl Into<String>,
     ) -> Self {
-        self.headers.insert(name.into().to_lowercase(), val.into().into());
+        self.headers.insert(name.into(), val.into());
+        self
+    }
+    /// Adds another value for the header, keeping any it already has, e.g. for several `Set-Cookie`s.
+    pub fn with_appended_header(
+        mut self,
+        name: impl Into<String>,
+        val: impl Into<String>,
+    ) -> Self {
+        self.headers.append(name.into(), val.into());
         self
     }
+    /// Sets a cookie with `Set-Cookie`. Every cookie gets its own header. Ref: RFC 6265 4.1
+    pub fn with_cookie(
+        self,
+        cookie: impl Into<Cookie<'static>>,
+    ) -> Self {
+        let cookie = cookie.into();
+        self.with_appended_header("Set-Cookie", cookie.encoded().to_string())
+    }
+    /// Tells the client to delete a cookie. The path and domain have to match the ones it was set with.
+    pub fn without_cookie(
+        self,
+        cookie: impl Into<Cookie<'static>>,
+    ) -> Self {
+        let mut cookie = cookie.into();
+        cookie.make_removal();
+        self.with_cookie(cookie)
+    }
 }
 
 impl Response {
-    pub fn as_bytes(&self) -> Vec<u8> {
+    /// Serializes the status line and headers, including the framing header for the body. Streams are only
+    /// chunk-encoded for clients that understand it - HTTP/1.0 clients get them as-is instead, and the end of
+    /// the body is marked by closing the connection. Ref: RFC 9112 6.3
+    fn head_bytes(
+        &self,
+        client_version: &HttpVersion,
+    ) -> Vec<u8> {
         let mut bytes = Vec::new();
         bytes.extend_from_slice(
             format!("{} {}", (&self.http_version as &str), self.status).as_bytes(),
         );
         bytes.extend_from_slice(b"\r\n");
-        for header in (&self.headers) as &HashMap<_, _> {
-            bytes.extend_from_slice(format!("{}: {}", &header.0, &header.1).as_bytes());
+        for (name, value) in &self.headers {
+            bytes.extend_from_slice(format!("{}: {}", name, value).as_bytes());
             bytes.extend_from_slice(b"\r\n");
         }
-        bytes.extend_from_slice(format!("{}: {}", "Content-Length", self.body.len()).as_bytes());
-        bytes.extend_from_slice(b"\r\n");
+        match &self.body {
+            ResponseBody::Bytes(body) => {
+                bytes.extend_from_slice(format!("{}: {}", "Content-Length", body.len()).as_bytes());
+                bytes.extend_from_slice(b"\r\n");
+            },
+            ResponseBody::Stream(_) if *client_version == HttpVersion::V1_0 => (),
+            ResponseBody::Stream(_) => {
+                bytes.extend_from_slice(
+                    format!("{}: {}", "Transfer-Encoding", "chunked").as_bytes(),
+                );
+                bytes.extend_from_slice(b"\r\n");
+            },
+        }
         bytes.extend_from_slice(b"\r\n");
-        bytes.extend_from_slice(&self.body);
+        bytes
+    }
 
+    /// Serializes a buffered response. Streaming bodies can't be buffered, so only the head is
+    /// returned for them - use `write_to` to send those.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.head_bytes(&HttpVersion::V1_1);
+        if let ResponseBody::Bytes(body) = &self.body {
+            bytes.extend_from_slice(body);
+        }
         bytes
     }
+
+    /// Writes only the status line and headers, in answer to a HEAD request. The headers still describe
+    /// the body that would have been sent for a GET. Ref: RFC 9110 9.3.2
+    pub async fn write_head_to<W: AsyncWrite + Unpin>(
+        self,
+        client_version: &HttpVersion,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        writer.write_all(&self.head_bytes(client_version)).await?;
+        writer.flush().await
+    }
+
+    /// Writes the full response to the client, streaming the body if needed. A streamed body sent to an
+    /// HTTP/1.0 client is only complete once the connection is closed.
+    pub async fn write_to<W: AsyncWrite + Unpin>(
+        self,
+        client_version: &HttpVersion,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        writer.write_all(&self.head_bytes(client_version)).await?;
+        self.body.write_to(writer, *client_version != HttpVersion::V1_0).await?;
+        writer.flush().await
+    }
 }
 
 #[derive(Clone, Deref)]
@@ -832,22 +1422,10 @@ where
     fn into_response(self) -> Response;
 }
 
+/// Serialized in the representation the client asked for with the `Accept` header, see [Serializers](super::negotiation::Serializers).
 impl<T: Serialize> IntoResponse for T {
     fn into_response(self) -> Response {
-        match serde_json::to_string(&self) {
-            Ok(body) => crate::application::http::route::Response {
-                status: crate::application::http::route::HttpStatus::Ok,
-                headers: Headers::from(vec![("Content-Type", "application/json")]), // TODO: Make this dynamic
-                http_version: crate::application::http::route::HttpVersion::V1_1,
-                body: body.into_bytes(),
-            },
-            Err(_) => crate::application::http::route::Response {
-                status: crate::application::http::route::HttpStatus::InternalServerError,
-                headers: Headers::from(vec![]),
-                http_version: crate::application::http::route::HttpVersion::V1_1,
-                body: Vec::new(),
-            },
-        }
+        serialize_response(&self)
     }
 }
 
@@ -862,6 +1440,33 @@ where
     Self: Sized,
 {
     fn from(req: Request) -> Result<Self, crate::Error>;
+
+    /// The response sent instead of calling the handler when extraction fails. By default, the error is
+    /// sent as problem details, including the field that couldn't be read. Extractors that can't override
+    /// it (e.g. `Deserialize` types) can be wrapped in [WithRejection](super::rejection::WithRejection).
+    fn rejection(err: crate::Error) -> Response {
+        err.into_response()
+    }
+}
+
+/// Extracts a handler argument from the [RequestContext]. Unlike `From<&RequestContext>`, extraction
+/// can fail, in which case the error is sent as the response and the handler is never called.
+pub trait FromRequestContext
+where
+    Self: Sized,
+{
+    fn from_request_context(ctx: &RequestContext) -> Result<Self, crate::Error>;
+
+    /// The response sent instead of calling the handler when extraction fails.
+    fn rejection(err: crate::Error) -> Response {
+        err.into_response()
+    }
+}
+
+impl<T: for<'a> From<&'a RequestContext>> FromRequestContext for T {
+    fn from_request_context(ctx: &RequestContext) -> Result<Self, crate::Error> {
+        Ok(T::from(ctx))
+    }
 }
 
 impl FromRequest for Request {
@@ -869,17 +1474,132 @@ impl FromRequest for Request {
         Ok(req)
     }
 }
-impl<T> FromRequest for PathVariable<T>
-where
-    T: From<String> + Display,
-{
+/// Scalars are read from the first path parameter, and tuples from the path parameters in order.
+impl<T: DeserializeOwned> FromRequest for PathVariable<T> {
     fn from(req: Request) -> Result<Self, crate::Error> {
-        // TODO: Not robust
-        match req.path_params.first() {
-            Some(val) => Ok(PathVariable(val.to_owned().into())),
-            None => Err("Unable to extract path variable".into()),
-        }
+        req.path_params.deserialize().map(PathVariable)
     }
 }
 
-use super::multipart::MultipartPart;
+#[cfg(test)]
+mod tests {
+    use super::{HttpMethod, HttpVersion, Response, Route, RouteError, RoutePolicy};
+
+    #[test]
+    fn matches_static_then_dynamic_then_wildcard() {
+        let route = Route::new()
+            .get("/static/index", || "index")
+            .get("/static/{name}", || "name")
+            .get("/static/{name}/raw", || "raw")
+            .get("/static/{*path}", || "path");
+        let find = |path: &str| {
+            let segments: Vec<String> = path.split('/').map(String::from).collect();
+            let mut params = Vec::new();
+            route.find_route(&segments, &mut params).map(|_| params)
+        };
+
+        assert_eq!(find("static/index"), Some(vec![]));
+        assert_eq!(find("static/logo.png"), Some(vec!["logo.png".to_string()]));
+        assert_eq!(find("static/logo.png/raw"), Some(vec!["logo.png".to_string()]));
+        // The dynamic branch is a dead end here, so the wildcard picks it up.
+        assert_eq!(find("static/css/site.css"), Some(vec!["css/site.css".to_string()]));
+        assert_eq!(find("other"), None);
+    }
+
+    #[test]
+    fn nests_routes_under_parsed_paths() {
+        let mut route = Route::new().get("/api/v1/health", || "ok");
+        route.route("api/v1/{tenant}/events", Route::new().get("/{event_id}", || "event"));
+
+        let segments: Vec<String> = "api/v1/acme/events/42".split('/').map(String::from).collect();
+        let mut params = Vec::new();
+        let node = route.find_route(&segments, &mut params).expect("Nested route should match");
+        assert_eq!(params, vec!["acme".to_string(), "42".to_string()]);
+        let handler = node.handlers.values().next().unwrap();
+        assert_eq!(handler.param_names, vec!["tenant".to_string(), "event_id".to_string()]);
+
+        let segments: Vec<String> = "api/v1/health".split('/').map(String::from).collect();
+        assert!(route.find_route(&segments, &mut Vec::new()).is_some());
+    }
+
+    #[test]
+    fn collects_route_errors() {
+        let route = Route::new()
+            .get("/files/{*path}/raw", || "raw")
+            .get("/event/{id}/{id}", || "event")
+            .get("/bad{segment", || "bad")
+            .get("/a-b.c~d/%20", || "pchar")
+            .get("/a-b.c~d/%20", || "duplicate");
+
+        assert_eq!(
+            route.errors(),
+            &[
+                RouteError::MisplacedWildcard {
+                    path: "/files/{*path}/raw".into()
+                },
+                RouteError::AmbiguousParameter {
+                    name: "id".into(),
+                    path: "/event/{id}/{id}".into()
+                },
+                RouteError::InvalidSegment {
+                    segment: "bad{segment".into(),
+                    path: "/bad{segment".into()
+                },
+                RouteError::DuplicateHandler {
+                    method: "GET".into(),
+                    path: "/a-b.c~d/%20".into()
+                },
+            ]
+        );
+        assert!(Route::new()
+            .try_with_handler(HttpMethod::Get, "/ok/{id}", || "ok", RoutePolicy::Public)
+            .is_ok());
+    }
+
+    #[test]
+    fn leaves_hidden_handlers_out_of_allow() {
+        let route = Route::new()
+            .get_with_policy("/admin", || "admin", RoutePolicy::RequireAuthentication.hidden())
+            .post("/admin", || "posted")
+            .delete_with_policy("/secret", || "gone", RoutePolicy::Public.hidden());
+        let find = |path: &str| {
+            let segments: Vec<String> = path.split('/').map(String::from).collect();
+            route.find_route(&segments, &mut Vec::new()).unwrap()
+        };
+
+        assert_eq!(find("admin").allowed_methods().as_deref(), Some("OPTIONS, POST"));
+        assert_eq!(find("secret").allowed_methods(), None);
+    }
+
+    #[tokio::test]
+    async fn streams_are_only_chunked_for_http_1_1() {
+        let stream = || {
+            let chunks: Vec<std::io::Result<Vec<u8>>> =
+                vec![Ok(b"Hello, ".to_vec()), Ok(b"world".to_vec())];
+            Response::ok().with_body_stream(Box::pin(futures::stream::iter(chunks)))
+        };
+
+        let mut written = Vec::new();
+        stream().write_to(&HttpVersion::V1_1, &mut written).await.unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(written.ends_with("\r\n\r\n7\r\nHello, \r\n5\r\nworld\r\n0\r\n\r\n"));
+
+        let mut written = Vec::new();
+        stream().write_to(&HttpVersion::V1_0, &mut written).await.unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(!written.contains("Transfer-Encoding"));
+        assert!(written.ends_with("\r\n\r\nHello, world"));
+    }
+
+    #[tokio::test]
+    async fn rejects_request_lines_over_the_max_length() {
+        let target = "a".repeat(super::ConfigConstants::request_line_max_length() as usize);
+        let raw = format!("GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n", target);
+        let mut stream = tokio::io::BufReader::new(raw.as_bytes());
+        assert!(matches!(
+            super::Request::read_next(&mut stream).await,
+            Err(crate::Error::BadRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,14 @@
+/// A problem with a route definition, found while registering it.
+/// Infallible route builders (e.g. `Route::get`) collect these, and they are reported by
+/// `WebServiceBuilder::build_service`.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum RouteError {
+    #[error("{method} {path} already has a handler")]
+    DuplicateHandler { method: String, path: String },
+    #[error("Invalid segment '{segment}' in route {path}")]
+    InvalidSegment { segment: String, path: String },
+    #[error("Path parameter {{{name}}} is used more than once in route {path}")]
+    AmbiguousParameter { name: String, path: String },
+    #[error("Wildcard segments are only allowed at the end of a handler's path: {path}")]
+    MisplacedWildcard { path: String },
+}
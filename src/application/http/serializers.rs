@@ -0,0 +1,280 @@
+use std::fmt::Display;
+
+use serde::de::value::SeqDeserializer;
+
+use super::{negotiation::MediaRange, path_params::ParamsDeserializer};
+
+/// Hands an erased deserializer for a request body to the caller, which picks the type to deserialize into.
+pub type DeserializeFn<'a> =
+    &'a mut dyn FnMut(&mut dyn erased_serde::Deserializer<'_>) -> Result<(), erased_serde::Error>;
+
+/// Converts response bodies to, and request bodies from, a single representation. Serializers are
+/// registered with [Serializers](super::negotiation::Serializers), and chosen through the `Accept` and
+/// `Content-Type` headers.
+pub trait ContentSerializer: Send + Sync {
+    /// The media types this serializer handles. The first is sent as the response's `Content-Type`,
+    /// any others are accepted as aliases.
+    fn media_types(&self) -> &[&'static str];
+
+    /// Whether this serializer can produce a representation for the given `Accept` media range.
+    fn accepts(
+        &self,
+        range: &MediaRange,
+    ) -> bool {
+        self.media_types().iter().any(|media_type| range.matches(media_type))
+    }
+
+    /// Serializes a response body. Fails with `406 Not Acceptable` if this representation can't
+    /// express the value, so that the next acceptable serializer gets a chance.
+    fn serialize(
+        &self,
+        value: &dyn erased_serde::Serialize,
+    ) -> Result<Vec<u8>, crate::Error>;
+
+    /// Deserializes a request body. Not every representation can be read back, so by default the
+    /// body is rejected with `415 Unsupported Media Type`.
+    fn deserialize(
+        &self,
+        _bytes: &[u8],
+        _visit: DeserializeFn,
+    ) -> Result<(), crate::Error> {
+        crate::Error::unsupported_media_type()
+    }
+}
+
+impl std::fmt::Debug for dyn ContentSerializer {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        f.debug_tuple("ContentSerializer").field(&self.media_types()).finish()
+    }
+}
+
+fn serialize_error(err: impl Display) -> crate::Error {
+    crate::Error::InternalServerError(format!("Failed to serialize response: {}", err))
+}
+
+fn deserialize_error(err: impl Display) -> crate::Error {
+    crate::Error::BadRequest(format!("Failed to deserialize request body: {}", err))
+}
+
+/// Whether the range asks for indented JSON, with a `pretty` param (other than `pretty=false`).
+fn wants_pretty(range: &MediaRange) -> bool {
+    range.param("pretty").is_some_and(|pretty| pretty != "false")
+}
+
+/// `application/json`
+pub struct Json;
+
+impl ContentSerializer for Json {
+    fn media_types(&self) -> &[&'static str] {
+        &["application/json"]
+    }
+
+    /// Leaves ranges asking for indented JSON to [PrettyJson], which would otherwise lose the tie.
+    fn accepts(
+        &self,
+        range: &MediaRange,
+    ) -> bool {
+        range.matches("application/json") && !wants_pretty(range)
+    }
+
+    fn serialize(
+        &self,
+        value: &dyn erased_serde::Serialize,
+    ) -> Result<Vec<u8>, crate::Error> {
+        serde_json::to_vec(value).map_err(serialize_error)
+    }
+
+    fn deserialize(
+        &self,
+        bytes: &[u8],
+        visit: DeserializeFn,
+    ) -> Result<(), crate::Error> {
+        let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+        visit(&mut <dyn erased_serde::Deserializer>::erase(&mut deserializer))
+            .map_err(deserialize_error)?;
+        deserializer.end().map_err(deserialize_error)
+    }
+}
+
+/// Indented JSON, for humans. Only chosen when asked for with `Accept: application/json; pretty`.
+pub struct PrettyJson;
+
+impl ContentSerializer for PrettyJson {
+    fn media_types(&self) -> &[&'static str] {
+        &["application/json"]
+    }
+
+    fn accepts(
+        &self,
+        range: &MediaRange,
+    ) -> bool {
+        range.matches("application/json") && wants_pretty(range)
+    }
+
+    fn serialize(
+        &self,
+        value: &dyn erased_serde::Serialize,
+    ) -> Result<Vec<u8>, crate::Error> {
+        serde_json::to_vec_pretty(value).map_err(serialize_error)
+    }
+
+    fn deserialize(
+        &self,
+        bytes: &[u8],
+        visit: DeserializeFn,
+    ) -> Result<(), crate::Error> {
+        Json.deserialize(bytes, visit)
+    }
+}
+
+/// `application/cbor` (Ref: RFC 8949)
+pub struct Cbor;
+
+impl ContentSerializer for Cbor {
+    fn media_types(&self) -> &[&'static str] {
+        &["application/cbor"]
+    }
+
+    fn serialize(
+        &self,
+        value: &dyn erased_serde::Serialize,
+    ) -> Result<Vec<u8>, crate::Error> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(value, &mut bytes).map_err(serialize_error)?;
+        Ok(bytes)
+    }
+
+    fn deserialize(
+        &self,
+        bytes: &[u8],
+        visit: DeserializeFn,
+    ) -> Result<(), crate::Error> {
+        // ciborium doesn't expose its deserializer, so the body is decoded into a value first.
+        let value: serde_json::Value =
+            ciborium::de::from_reader(bytes).map_err(deserialize_error)?;
+        visit(&mut <dyn erased_serde::Deserializer>::erase(value)).map_err(deserialize_error)
+    }
+}
+
+/// `application/msgpack`. Structs are written as maps, so fields are matched up by name.
+pub struct MessagePack;
+
+impl ContentSerializer for MessagePack {
+    fn media_types(&self) -> &[&'static str] {
+        &["application/msgpack", "application/vnd.msgpack", "application/x-msgpack"]
+    }
+
+    fn serialize(
+        &self,
+        value: &dyn erased_serde::Serialize,
+    ) -> Result<Vec<u8>, crate::Error> {
+        rmp_serde::to_vec_named(value).map_err(serialize_error)
+    }
+
+    fn deserialize(
+        &self,
+        bytes: &[u8],
+        visit: DeserializeFn,
+    ) -> Result<(), crate::Error> {
+        let mut deserializer = rmp_serde::Deserializer::new(bytes);
+        visit(&mut <dyn erased_serde::Deserializer>::erase(&mut deserializer))
+            .map_err(deserialize_error)
+    }
+}
+
+/// `text/csv` (Ref: RFC 4180), for lists only. Each item is a row, with a header row taken from the
+/// first item's fields, in the order they're declared. Values are parsed back with `FromStr`, like path parameters.
+pub struct Csv;
+
+impl ContentSerializer for Csv {
+    fn media_types(&self) -> &[&'static str] {
+        &["text/csv"]
+    }
+
+    fn serialize(
+        &self,
+        value: &dyn erased_serde::Serialize,
+    ) -> Result<Vec<u8>, crate::Error> {
+        let serde_json::Value::Array(rows) =
+            serde_json::to_value(value).map_err(serialize_error)?
+        else {
+            Err(crate::Error::NotAcceptable)?
+        };
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        let columns: Vec<String> = match rows.first() {
+            Some(serde_json::Value::Object(first)) => first.keys().cloned().collect(),
+            _ => Vec::new(),
+        };
+        if !columns.is_empty() {
+            writer.write_record(&columns).map_err(serialize_error)?;
+        }
+        for row in &rows {
+            let record: Vec<String> = match row {
+                serde_json::Value::Object(fields) => {
+                    columns.iter().map(|column| csv_cell(fields.get(column))).collect()
+                },
+                value => vec![csv_cell(Some(value))],
+            };
+            writer.write_record(&record).map_err(serialize_error)?;
+        }
+        writer.into_inner().map_err(serialize_error)
+    }
+
+    fn deserialize(
+        &self,
+        bytes: &[u8],
+        visit: DeserializeFn,
+    ) -> Result<(), crate::Error> {
+        let mut reader = csv::Reader::from_reader(bytes);
+        let columns = reader.headers().map_err(deserialize_error)?.clone();
+        let mut rows = Vec::new();
+        for record in reader.records() {
+            let record = record.map_err(deserialize_error)?;
+            let row: Vec<(String, String)> = columns
+                .iter()
+                .zip(record.iter())
+                .map(|(column, value)| (column.to_string(), value.to_string()))
+                .collect();
+            rows.push(row);
+        }
+        let rows = SeqDeserializer::new(rows.iter().map(|row| ParamsDeserializer(row)));
+        visit(&mut <dyn erased_serde::Deserializer>::erase(rows)).map_err(deserialize_error)
+    }
+}
+
+fn csv_cell(value: Option<&serde_json::Value>) -> String {
+    match value {
+        None | Some(serde_json::Value::Null) => String::new(),
+        Some(serde_json::Value::String(value)) => value.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+/// `application/yaml` (Ref: RFC 9512)
+pub struct Yaml;
+
+impl ContentSerializer for Yaml {
+    fn media_types(&self) -> &[&'static str] {
+        &["application/yaml", "application/x-yaml", "text/yaml"]
+    }
+
+    fn serialize(
+        &self,
+        value: &dyn erased_serde::Serialize,
+    ) -> Result<Vec<u8>, crate::Error> {
+        serde_yaml::to_string(value).map(String::into_bytes).map_err(serialize_error)
+    }
+
+    fn deserialize(
+        &self,
+        bytes: &[u8],
+        visit: DeserializeFn,
+    ) -> Result<(), crate::Error> {
+        let deserializer = serde_yaml::Deserializer::from_slice(bytes);
+        visit(&mut <dyn erased_serde::Deserializer>::erase(deserializer)).map_err(deserialize_error)
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    io::{Read, Write},
+    pin::Pin,
+    sync::Arc,
+};
+
+use flate2::{
+    read::{GzDecoder, ZlibDecoder},
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+
+use crate::application::{
+    http::{
+        headers::Headers,
+        response_body::ResponseBody,
+        route::{HttpBody, IntoResponse, Request, RequestContext, Response},
+    },
+    ConfigConstants, NextFn,
+};
+
+/// Bodies smaller than this aren't worth compressing, since the coding's overhead can make them larger.
+const MIN_COMPRESSIBLE_LENGTH: usize = 1024;
+
+/// Brotli's quality goes up to 11, but the higher levels are far too slow to use on every response.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const BROTLI_WINDOW_SIZE: u32 = 22;
+
+/// The content codings we can produce and decode, in order of preference. Ref: RFC 9110 8.4.1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentCoding {
+    Brotli,
+    Gzip,
+    /// The zlib format, despite the name. Ref: RFC 9110 8.4.1.2
+    Deflate,
+}
+
+impl ContentCoding {
+    const ALL: [ContentCoding; 3] =
+        [ContentCoding::Brotli, ContentCoding::Gzip, ContentCoding::Deflate];
+
+    pub fn token(&self) -> &'static str {
+        match self {
+            ContentCoding::Brotli => "br",
+            ContentCoding::Gzip => "gzip",
+            ContentCoding::Deflate => "deflate",
+        }
+    }
+
+    pub fn from_token(token: &str) -> Option<Self> {
+        match token.trim().to_lowercase().as_str() {
+            "br" => Some(ContentCoding::Brotli),
+            "gzip" | "x-gzip" => Some(ContentCoding::Gzip),
+            "deflate" => Some(ContentCoding::Deflate),
+            _ => None,
+        }
+    }
+
+    pub fn encode(
+        &self,
+        bytes: &[u8],
+    ) -> std::io::Result<Vec<u8>> {
+        match self {
+            ContentCoding::Brotli => {
+                let mut encoded = Vec::new();
+                {
+                    let mut writer = brotli::CompressorWriter::new(
+                        &mut encoded,
+                        BROTLI_BUFFER_SIZE,
+                        BROTLI_QUALITY,
+                        BROTLI_WINDOW_SIZE,
+                    );
+                    writer.write_all(bytes)?;
+                }
+                Ok(encoded)
+            },
+            ContentCoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            },
+            ContentCoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            },
+        }
+    }
+
+    /// Decodes a body, failing with `413 Entity Too Large` as soon as it grows past `max_length`,
+    /// so a small compressed body can't expand into an enormous one.
+    pub fn decode(
+        &self,
+        bytes: &[u8],
+        max_length: u64,
+    ) -> Result<Vec<u8>, crate::Error> {
+        let decoder: Box<dyn Read + '_> = match self {
+            ContentCoding::Brotli => Box::new(brotli::Decompressor::new(bytes, BROTLI_BUFFER_SIZE)),
+            ContentCoding::Gzip => Box::new(GzDecoder::new(bytes)),
+            ContentCoding::Deflate => Box::new(ZlibDecoder::new(bytes)),
+        };
+        let mut decoded = Vec::new();
+        decoder.take(max_length + 1).read_to_end(&mut decoded).map_err(|err| {
+            log::warn!("[BAD REQUEST] Failed to decode {} body: {}", self.token(), err);
+            crate::Error::BadRequest(format!("Invalid {} body.", self.token()))
+        })?;
+        if decoded.len() as u64 > max_length {
+            crate::Error::entity_too_large()?;
+        }
+        Ok(decoded)
+    }
+}
+
+/// Whether the body was sent with a content coding, and has to be decoded before it can be parsed.
+pub fn is_content_encoded(headers: &Headers) -> bool {
+    headers.get("content-encoding").is_some_and(|encoding| {
+        encoding
+            .split(',')
+            .map(str::trim)
+            .any(|c| !c.is_empty() && !c.eq_ignore_ascii_case("identity"))
+    })
+}
+
+/// Picks the coding for a response from the `Accept-Encoding` header. Ref: RFC 9110 12.5.3
+/// Among codings with the same quality, our own order of preference wins.
+pub fn preferred_coding(accept_encoding: &str) -> Option<ContentCoding> {
+    let accepted: Vec<(String, f32)> = accept_encoding
+        .split(',')
+        .filter_map(|coding| {
+            let mut parts = coding.split(';');
+            let token = parts.next()?.trim().to_lowercase();
+            let quality = match parts.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(quality) => quality.trim().parse().ok()?,
+                None => 1.0,
+            };
+            (!token.is_empty()).then_some((token, quality))
+        })
+        .collect();
+    let quality = |coding: ContentCoding| {
+        accepted
+            .iter()
+            .find(|(token, _)| ContentCoding::from_token(token) == Some(coding))
+            .or_else(|| accepted.iter().find(|(token, _)| token == "*"))
+            .map_or(0.0, |(_, quality)| *quality)
+    };
+
+    let mut best: Option<(ContentCoding, f32)> = None;
+    for coding in ContentCoding::ALL {
+        let quality = quality(coding);
+        if quality > best.map_or(0.0, |(_, best)| best) {
+            best = Some((coding, quality));
+        }
+    }
+    best.map(|(coding, _)| coding)
+}
+
+/// Only textual media types are worth compressing. Images, audio, video, and archives are already
+/// compressed, so they're sent as they are.
+fn is_compressible(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim().to_lowercase();
+    media_type.starts_with("text/")
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+        || matches!(
+            media_type.as_str(),
+            "application/json"
+                | "application/xml"
+                | "application/javascript"
+                | "application/yaml"
+                | "application/x-yaml"
+                | "application/wasm"
+        )
+}
+
+/// Adds a header name to `Vary`, keeping whatever is already there (e.g. `Accept` from content negotiation).
+fn add_vary(
+    response: Response,
+    header: &str,
+) -> Response {
+    let vary = match response.headers.get("vary").map(|vary| vary.to_string()) {
+        Some(vary) if vary.split(',').any(|name| name.trim().eq_ignore_ascii_case(header)) => {
+            return response;
+        },
+        Some(vary) => format!("{}, {}", vary, header),
+        None => header.to_string(),
+    };
+    response.with_header("Vary", vary)
+}
+
+/// Decodes a compressed request body, and runs it through the regular body parsers.
+/// Bodies with a content coding are kept as raw bytes when they're read, until this decodes them.
+async fn decode_request_body(req: &mut Request) -> Result<(), crate::Error> {
+    if !is_content_encoded(&req.headers) {
+        return Ok(());
+    }
+    let HttpBody::Bytes(mut bytes) = std::mem::replace(&mut req.body, HttpBody::None) else {
+        return Ok(());
+    };
+    let content_encoding = req
+        .headers
+        .get("content-encoding")
+        .map(|encoding| encoding.to_string())
+        .unwrap_or_default();
+    // Codings are listed in the order they were applied, so they're undone in reverse. Ref: RFC 9110 8.4
+    for token in content_encoding.split(',').rev().map(str::trim) {
+        if token.is_empty() || token.eq_ignore_ascii_case("identity") {
+            continue;
+        }
+        let Some(coding) = ContentCoding::from_token(token) else {
+            crate::Error::unsupported_media_type()?
+        };
+        bytes = coding.decode(&bytes, ConfigConstants::max_content_length())?;
+    }
+    req.headers.remove("content-encoding");
+    req.body = HttpBody::parse(&req.headers, bytes).await?;
+    Ok(())
+}
+
+/// Compresses the response body, if the client accepts a coding we support and it's worth it.
+fn compress_response(
+    response: Response,
+    accept_encoding: Option<&str>,
+) -> Response {
+    let is_compressible = matches!(response.body, ResponseBody::Bytes(_))
+        && !response.headers.contains_key("content-encoding")
+        && response.headers.get("content-type").is_some_and(|ct| is_compressible(ct));
+    if !is_compressible {
+        return response;
+    }
+    // The representation now depends on Accept-Encoding, even when this one isn't compressed. Ref: RFC 9110 12.5.5
+    let response = add_vary(response, "Accept-Encoding");
+
+    let (ResponseBody::Bytes(body), Some(coding)) =
+        (&response.body, accept_encoding.and_then(preferred_coding))
+    else {
+        return response;
+    };
+    if body.len() < MIN_COMPRESSIBLE_LENGTH {
+        return response;
+    }
+    match coding.encode(body) {
+        Ok(encoded) if encoded.len() < body.len() => {
+            response.with_body(encoded).with_header("Content-Encoding", coding.token())
+        },
+        Ok(_) => response,
+        Err(err) => {
+            log::warn!("Failed to compress response with {}: {:?}", coding.token(), err);
+            response
+        },
+    }
+}
+
+/// Compresses responses with the best coding the client accepts, and decodes compressed request
+/// bodies (`Content-Encoding`) before they reach the route handlers.
+///
+/// Streamed bodies, small bodies, and media types that are already compressed (e.g. images) are
+/// sent uncompressed.
+pub fn handle_compression(
+    mut req: Request,
+    ctx: RequestContext,
+    next: Arc<NextFn>,
+) -> Pin<Box<dyn Send + std::future::Future<Output = Response>>> {
+    Box::pin(async move {
+        match decode_request_body(&mut req).await {
+            Ok(()) => {},
+            // Tell the client which codings it can use instead. Ref: RFC 9110 12.5.3
+            Err(crate::Error::UnsupportedMediaType) => {
+                return Response::unsupported_media_type()
+                    .with_header("Accept-Encoding", "br, gzip, deflate");
+            },
+            Err(err) => return err.into_response(),
+        }
+        let accept_encoding = req.headers.get("accept-encoding").map(|ae| ae.to_string());
+        let response = next(req, ctx).await;
+        compress_response(response, accept_encoding.as_deref())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{preferred_coding, ContentCoding};
+
+    #[test]
+    fn negotiates_codings() {
+        assert_eq!(preferred_coding("gzip, deflate, br"), Some(ContentCoding::Brotli));
+        assert_eq!(preferred_coding("gzip;q=1.0, br;q=0.5"), Some(ContentCoding::Gzip));
+        assert_eq!(preferred_coding("*;q=0.1, br;q=0"), Some(ContentCoding::Gzip));
+        assert_eq!(preferred_coding("identity"), None);
+        assert_eq!(preferred_coding(""), None);
+    }
+
+    #[test]
+    fn round_trips_and_limits_decoded_length() {
+        let body = "{\"name\":\"compressible\"}".repeat(100).into_bytes();
+        for coding in ContentCoding::ALL {
+            let encoded = coding.encode(&body).unwrap();
+            assert!(encoded.len() < body.len());
+            assert_eq!(coding.decode(&encoded, body.len() as u64).unwrap(), body);
+            assert!(coding.decode(&encoded, 100).is_err());
+        }
+    }
+}
@@ -78,16 +78,24 @@ pub fn handle_cors(
         // TODO: Not the proper way to check, but "good enough" to unblock.
         // THIS CORS MIDDLEWARE IS WIDE OPEN RIGHT NOW don't rely on it for actual security
         if matches!(req.method, HttpMethod::Options) {
-            Response::ok()
-                .with_header(
-                    CorsHeader::AccessControlAllowOrigin.to_string(),
-                    req.headers
-                        .get(&CorsHeader::Origin.to_string())
-                        .map_or("*".to_string(), |s| s.to_string()),
-                )
+            let origin = req
+                .headers
+                .get(&CorsHeader::Origin.to_string())
+                .map_or("*".to_string(), |s| s.to_string());
+            // The route tree answers OPTIONS with an `Allow` header listing the methods the path supports,
+            // which is exactly what the preflight needs to advertise.
+            let response = next(req, ctx).await;
+            let allowed_methods = response.headers.get("allow").map(|allow| allow.to_string());
+            let response = response
+                .with_header(CorsHeader::AccessControlAllowOrigin.to_string(), origin)
                 .with_header(CorsHeader::AccessControlAllowCredentials, "true")
-                .with_header(CorsHeader::AccessControlAllowHeaders, "origin, content-type, accept")
-                .with_header(CorsHeader::AccessControlAllowMethods, "GET, POST, DELETE, PATCH")
+                .with_header(CorsHeader::AccessControlAllowHeaders, "origin, content-type, accept");
+            match allowed_methods {
+                Some(methods) => {
+                    response.with_header(CorsHeader::AccessControlAllowMethods, methods)
+                },
+                None => response,
+            }
         } else if req.headers.contains_key("origin") {
             // Stash them for later - afterware will have to pull this
             let origin = req
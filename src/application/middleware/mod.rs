@@ -1 +1,2 @@
+pub mod compression;
 pub mod cors;
@@ -6,6 +6,7 @@ pub mod middleware;
 pub mod static_files;
 pub mod stats;
 pub mod threads;
+pub mod tls;
 pub use std::cell::OnceCell;
 
 mod webhook;
@@ -40,4 +41,19 @@ impl ConfigConstants {
         "MAX_CONTENT_LENGTH",
         MAX_CONTENT_LENGTH = (50 * 1024 * 1024)
     ); // 50 MB
+    const_from_env!(
+        multipart_max_length,
+        "MULTIPART_MAX_LENGTH",
+        MULTIPART_MAX_LENGTH = (500 * 1024 * 1024)
+    ); // 500 MB
+    const_from_env!(
+        multipart_max_part_length,
+        "MULTIPART_MAX_PART_LENGTH",
+        MULTIPART_MAX_PART_LENGTH = (100 * 1024 * 1024)
+    ); // 100 MB
+    const_from_env!(
+        multipart_spill_threshold,
+        "MULTIPART_SPILL_THRESHOLD",
+        MULTIPART_SPILL_THRESHOLD = (1024 * 1024)
+    ); // 1 MB
 }
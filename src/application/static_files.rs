@@ -1,4 +1,8 @@
-use std::{collections::HashMap, io::Read, path::Path};
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Component, Path},
+};
 
 use tailwag_utils::{files::FileWalker, strings::SanitizeXml, templates::templatize_file};
 
@@ -18,7 +22,16 @@ impl StaticFiles {
             file?.read_to_end(&mut bytes)?;
             static_files.files.insert(
                 // This is a mouthful. Probably an easier way to simplify this?
-                path.strip_prefix(&path)?.to_str().ok_or("".to_string())?.to_string(),
+                path.strip_prefix(&path)
+                    .ok()
+                    .and_then(|path| path.to_str())
+                    .ok_or_else(|| {
+                        crate::Error::InternalServerError(format!(
+                            "Invalid static file path: {}",
+                            path.display()
+                        ))
+                    })?
+                    .to_string(),
                 bytes,
             );
         }
@@ -106,6 +119,10 @@ fn get_content_type(filename: &str) -> &'static str {
 
 pub fn load_static(filename: PathVar<String>) -> Response {
     let filename = filename.0;
+    // Only plain file / directory names are allowed, so requests can't escape the static directory.
+    if !Path::new(&filename).components().all(|part| matches!(part, Component::Normal(_))) {
+        return Response::not_found();
+    }
     let Ok(mut body) = std::fs::read(format!("static/{}", filename)) else {
         return Response::bad_request();
     };
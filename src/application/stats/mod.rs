@@ -1,5 +1,34 @@
-#[derive(Debug)]
-pub struct Statistics {}
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// Counters kept while the service runs. They're shared by every connection, so they're atomic.
+#[derive(Debug, Default)]
+pub struct Statistics {
+    requests: AtomicU64,
+    panics: AtomicU64,
+}
+
+impl Statistics {
+    pub fn record_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_panic(&self) {
+        self.panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The number of requests passed to the handlers.
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// The number of requests whose handler panicked, and were answered with a `500` instead.
+    pub fn panics(&self) -> u64 {
+        self.panics.load(Ordering::Relaxed)
+    }
+}
 
 #[derive(Debug)]
 pub enum TerminationStatus {
@@ -11,14 +40,14 @@ pub enum TerminationStatus {
 
 #[derive(Debug)]
 pub struct RunResult {
-    pub statistics: Statistics,
+    pub statistics: Arc<Statistics>,
     pub termination_status: TerminationStatus,
 }
 
 impl Default for RunResult {
     fn default() -> Self {
         Self {
-            statistics: Statistics {},
+            statistics: Default::default(),
             termination_status: TerminationStatus::Terminated,
         }
     }
@@ -0,0 +1,180 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::application::web_service::http2::ALPN_H2;
+
+use tokio_rustls::{
+    rustls::{self, pki_types::CertificateDer, server::WebPkiClientVerifier, RootCertStore},
+    TlsAcceptor,
+};
+
+/// Configuration for terminating TLS directly in the service.
+///
+/// Configured from the `TLS_CERT_PATH` and `TLS_KEY_PATH` env variables. Client certificates are
+/// required and verified against `TLS_CLIENT_CA_PATH`, if it is set.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM file containing the certificate chain, leaf certificate first.
+    pub cert_path: PathBuf,
+    /// PEM file containing the private key for the leaf certificate.
+    pub key_path: PathBuf,
+    /// PEM file containing the CA certificates trusted to sign client certificates.
+    pub client_ca_path: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    pub fn new(
+        cert_path: impl Into<PathBuf>,
+        key_path: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            client_ca_path: None,
+        }
+    }
+
+    /// Require clients to present a certificate signed by one of the CAs in `ca_path`.
+    pub fn with_client_cert_verification(
+        mut self,
+        ca_path: impl Into<PathBuf>,
+    ) -> Self {
+        self.client_ca_path = Some(ca_path.into());
+        self
+    }
+
+    pub fn from_env() -> Option<Self> {
+        let cert_path = std::env::var("TLS_CERT_PATH").ok()?;
+        let key_path = std::env::var("TLS_KEY_PATH").ok()?;
+        let config = Self::new(cert_path, key_path);
+        Some(match std::env::var("TLS_CLIENT_CA_PATH") {
+            Ok(ca_path) => config.with_client_cert_verification(ca_path),
+            Err(_) => config,
+        })
+    }
+
+    /// Reads the certificates and key from disk and builds an acceptor from them.
+    /// Called on startup, and again whenever the certificates are reloaded.
+    pub fn load_acceptor(&self) -> Result<TlsAcceptor, crate::Error> {
+        let certs = load_certs(&self.cert_path)?;
+        let key = open(&self.key_path)
+            .and_then(|mut file| rustls_pemfile::private_key(&mut file).map_err(tls_error))?
+            .ok_or(crate::Error::InternalServerError(format!(
+                "No private key found in {}",
+                self.key_path.display()
+            )))?;
+
+        // The provider is picked explicitly, so we don't depend on which crypto features other crates enable.
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?;
+        let builder = match &self.client_ca_path {
+            Some(ca_path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in load_certs(ca_path)? {
+                    roots.add(cert).map_err(tls_error)?;
+                }
+                builder.with_client_cert_verifier(
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()
+                        .map_err(tls_error)?,
+                )
+            },
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder.with_single_cert(certs, key).map_err(tls_error)?;
+        config.alpn_protocols = vec![ALPN_H2.to_vec(), b"http/1.1".to_vec()];
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// TLS misconfiguration is a server-side problem, never the client's.
+fn tls_error(err: impl std::fmt::Display) -> crate::Error {
+    crate::Error::InternalServerError(format!("TLS configuration error: {err}"))
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, crate::Error> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| tls_error(format!("Unable to open {}: {err}", path.display())))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, crate::Error> {
+    let certs = rustls_pemfile::certs(&mut open(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(tls_error)?;
+    if certs.is_empty() {
+        Err(tls_error(format!("No certificates found in {}", path.display())))?;
+    }
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use tempfile::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{
+        rustls::{self, pki_types::ServerName, RootCertStore},
+        TlsConnector,
+    };
+
+    use super::TlsConfig;
+
+    /// Writes a freshly generated self-signed certificate for `localhost` to a temp dir, which is removed
+    /// when the returned `TempDir` is dropped.
+    fn self_signed_cert() -> (TempDir, TlsConfig, rcgen::CertifiedKey) {
+        let dir = tempfile::tempdir().unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let cert_path: PathBuf = dir.path().join("cert.pem");
+        let key_path: PathBuf = dir.path().join("key.pem");
+        std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+        std::fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+        (dir, TlsConfig::new(cert_path, key_path), certified)
+    }
+
+    #[tokio::test]
+    async fn handshakes_with_self_signed_cert() {
+        let (_dir, config, certified) = self_signed_cert();
+        let acceptor = config.load_acceptor().unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let client_config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        let connector = TlsConnector::from(Arc::new(client_config));
+
+        let (client, server) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move {
+            let mut stream = acceptor.accept(server).await.unwrap();
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            buf
+        });
+        let mut client =
+            connector.connect(ServerName::try_from("localhost").unwrap(), client).await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        client.flush().await.unwrap();
+
+        assert_eq!(&server.await.unwrap(), b"ping");
+    }
+
+    #[test]
+    fn fails_without_key() {
+        let (_dir, config, _) = self_signed_cert();
+        std::fs::write(&config.key_path, "").unwrap();
+        assert!(config.load_acceptor().is_err());
+    }
+}
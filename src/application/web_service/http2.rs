@@ -0,0 +1,437 @@
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::StreamExt;
+use h2::server::SendResponse;
+use h2::{RecvStream, SendStream};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::time::timeout;
+use tokio_util::io::StreamReader;
+
+use crate::application::http::headers::Headers;
+use crate::application::http::multipart::{
+    read_streamed_multipart, streamed_multipart_params, MultipartLimits,
+};
+use crate::application::http::query::parse_query_params;
+use crate::application::http::response_body::ResponseBody;
+use crate::application::http::route::{
+    HttpBody, HttpVersion, Request, RequestContext, Response, ServerContext,
+};
+use crate::application::ConfigConstants;
+
+use super::{RequestMetrics, WebServiceInner};
+
+/// Every HTTP/2 connection starts with this preface, sent by the client. Ref: RFC 9113 3.4
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// The ALPN protocol ID clients use to ask for HTTP/2 over TLS. Ref: RFC 9113 3.2
+pub const ALPN_H2: &[u8] = b"h2";
+
+/// Connection-specific headers are not allowed in HTTP/2 responses. Ref: RFC 9113 8.2.2
+const CONNECTION_HEADERS: [&str; 5] =
+    ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"];
+
+/// Reads the start of a connection, for as long as it could still be the HTTP/2 preface. The preface can
+/// arrive over several reads, so it's only ruled out once a byte doesn't match (or the client stops sending).
+/// Returns everything that was read, to be put back with [Rewind].
+pub(super) async fn read_preface<S: AsyncRead + Unpin>(stream: &mut S) -> std::io::Result<Vec<u8>> {
+    let mut read = Vec::with_capacity(PREFACE.len());
+    let mut buf = [0; PREFACE.len()];
+    while read.len() < PREFACE.len() && PREFACE.starts_with(&read) {
+        let len = stream.read(&mut buf[..PREFACE.len() - read.len()]).await?;
+        if len == 0 {
+            break;
+        }
+        read.extend_from_slice(&buf[..len]);
+    }
+    Ok(read)
+}
+
+/// A stream with bytes that were already read off of it put back in front, so that whichever protocol ends
+/// up serving the connection sees it from the start.
+pub(super) struct Rewind<S> {
+    prefix: Bytes,
+    inner: S,
+}
+
+impl<S> Rewind<S> {
+    pub(super) fn new(
+        prefix: impl Into<Bytes>,
+        inner: S,
+    ) -> Self {
+        Self {
+            prefix: prefix.into(),
+            inner,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Rewind<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if self.prefix.is_empty() {
+            return Pin::new(&mut self.inner).poll_read(cx, buf);
+        }
+        let len = self.prefix.len().min(buf.remaining());
+        buf.put_slice(&self.prefix.split_to(len));
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Rewind<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl WebServiceInner {
+    /// Serves an HTTP/2 connection, negotiated either through ALPN or with prior knowledge (h2c).
+    /// Each stream is handled on its own task, so slow requests don't block the rest of the connection.
+    pub(super) async fn serve_http2<S>(
+        self,
+        stream: S,
+        peer_addr: SocketAddr,
+        server_context: ServerContext,
+    ) -> Result<RequestMetrics, crate::Error>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let idle_timeout = Duration::from_millis(self.config.keep_alive_timeout_ms);
+        let request_timeout = Duration::from_millis(self.config.request_timeout_seconds);
+
+        let mut connection = match timeout(request_timeout, h2::server::handshake(stream)).await {
+            Ok(connection) => connection?,
+            Err(_elapsed) => {
+                log::warn!("Timed out waiting for HTTP/2 handshake from {}", peer_addr);
+                return Ok(());
+            },
+        };
+
+        let mut shutting_down = false;
+        loop {
+            // Once we've sent GOAWAY, keep driving the connection until the in-flight streams finish.
+            let next = if shutting_down {
+                connection.accept().await
+            } else {
+                match timeout(idle_timeout, connection.accept()).await {
+                    Ok(next) => next,
+                    Err(_elapsed) => {
+                        log::debug!("Closing idle HTTP/2 connection from {}", peer_addr);
+                        connection.graceful_shutdown();
+                        shutting_down = true;
+                        continue;
+                    },
+                }
+            };
+            match next {
+                Some(Ok((request, respond))) => {
+                    let inner = self.clone();
+                    let context = server_context.clone();
+                    tokio::spawn(inner.handle_http2_stream(request, respond, context));
+                },
+                Some(Err(err)) => {
+                    log::warn!("HTTP/2 connection from {} failed: {:?}", peer_addr, err);
+                    break;
+                },
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_http2_stream(
+        self,
+        request: http::Request<RecvStream>,
+        mut respond: SendResponse<Bytes>,
+        server_context: ServerContext,
+    ) {
+        let request_id = uuid::Uuid::new_v4();
+        let request_timeout = Duration::from_millis(self.config.request_timeout_seconds);
+
+        let is_head = request.method() == http::Method::HEAD;
+        let max_length = ConfigConstants::max_content_length() as usize;
+        let response = match timeout(request_timeout, read_request(request, max_length)).await {
+            Ok(Ok(request)) => {
+                let context = RequestContext::from_server_context(server_context);
+                self.call_handler(request, context, request_id).await
+            },
+            Ok(Err(err)) => self.error_response(err, request_id),
+            Err(_elapsed) => {
+                log::warn!("[REQ_ID {request_id}] Timed out waiting for HTTP/2 request body");
+                respond.send_reset(h2::Reason::CANCEL);
+                return;
+            },
+        };
+
+        if let Err(err) = write_response(response, respond, is_head).await {
+            log::warn!("[REQ_ID {request_id}] Failed to write HTTP/2 response: {:?}", err);
+        }
+    }
+}
+
+/// Converts an HTTP/2 request into our own [Request], reading the full body (up to `max_length`) off of the stream.
+/// Multipart bodies are parsed as they arrive instead, with their own [MultipartLimits].
+async fn read_request(
+    request: http::Request<RecvStream>,
+    max_length: usize,
+) -> Result<Request, crate::Error> {
+    let (parts, mut stream) = request.into_parts();
+
+    let mut headers = Headers::default();
+    for (name, value) in &parts.headers {
+        let value = value
+            .to_str()
+            .map_err(|_| crate::Error::BadRequest(format!("Invalid header value for {}", name)))?;
+        headers.append(name.as_str(), value);
+    }
+    // Cookies may be split across several fields in HTTP/2, and have to be joined back together. Ref: RFC 9113 8.2.3
+    let cookies: Vec<String> = headers.get_all("cookie").map(|cookie| cookie.to_string()).collect();
+    if cookies.len() > 1 {
+        headers.insert("cookie", cookies.join("; "));
+    }
+    // `:authority` replaces the Host header. Ref: RFC 9113 8.3.1
+    if let (None, Some(authority)) = (headers.get("host"), parts.uri.authority()) {
+        headers.insert("host", authority.as_str());
+    }
+
+    let body = match streamed_multipart_params(&headers) {
+        Some(params) => {
+            let limits = MultipartLimits::default();
+            read_streamed_multipart(into_reader(stream), &params, &limits).await?
+        },
+        None => {
+            let mut bytes = Vec::new();
+            while let Some(chunk) = stream.data().await {
+                let chunk = chunk?;
+                if chunk.len() > max_length - bytes.len() {
+                    crate::Error::entity_too_large()?;
+                }
+                stream.flow_control().release_capacity(chunk.len())?;
+                bytes.extend_from_slice(&chunk);
+            }
+            if bytes.is_empty() {
+                HttpBody::None
+            } else {
+                HttpBody::parse(&headers, bytes).await?
+            }
+        },
+    };
+
+    let query = parts.uri.query().unwrap_or_default();
+    Ok(Request {
+        method: parts.method.as_str().try_into()?,
+        path: parts.uri.path().to_string(),
+        path_params: Default::default(),
+        query: query.to_string(),
+        query_params: parse_query_params(query),
+        http_version: HttpVersion::V2_0,
+        headers,
+        body,
+    })
+}
+
+/// The request body as an `AsyncRead`. Flow-control capacity is released as the data is read, so the
+/// client only sends more once we've caught up.
+fn into_reader(stream: RecvStream) -> impl AsyncRead + Unpin {
+    let chunks = futures::stream::unfold(stream, |mut stream| async move {
+        let chunk = stream.data().await?.and_then(|chunk| {
+            stream.flow_control().release_capacity(chunk.len())?;
+            Ok(chunk)
+        });
+        Some((chunk.map_err(std::io::Error::other), stream))
+    });
+    StreamReader::new(Box::pin(chunks))
+}
+
+/// Sends the response on its stream. Responses to HEAD requests keep their headers, but never send a body.
+async fn write_response(
+    response: Response,
+    mut respond: SendResponse<Bytes>,
+    is_head: bool,
+) -> Result<(), crate::Error> {
+    let mut head = http::Response::builder().status(response.status.clone() as usize as u16);
+    for (name, value) in response.headers.iter() {
+        if CONNECTION_HEADERS.contains(&name.to_lowercase().as_str()) {
+            continue;
+        }
+        head = head.header(name, value.as_str());
+    }
+
+    match response.body {
+        ResponseBody::Bytes(body) => {
+            let head = head.header("content-length", body.len()).body(())?;
+            let end_of_stream = is_head || body.is_empty();
+            let mut send = respond.send_response(head, end_of_stream)?;
+            if !end_of_stream {
+                send_data(&mut send, body.into(), true).await?;
+            }
+        },
+        ResponseBody::Stream(_) if is_head => {
+            respond.send_response(head.body(())?, true)?;
+        },
+        ResponseBody::Stream(mut stream) => {
+            let mut send = respond.send_response(head.body(())?, false)?;
+            while let Some(chunk) = stream.next().await {
+                send_data(&mut send, chunk?.into(), false).await?;
+            }
+            send.send_data(Bytes::new(), true)?;
+        },
+    }
+    Ok(())
+}
+
+/// Sends the data as the peer's flow-control window allows, instead of letting h2 buffer all of it.
+async fn send_data(
+    send: &mut SendStream<Bytes>,
+    mut data: Bytes,
+    end_of_stream: bool,
+) -> Result<(), crate::Error> {
+    while !data.is_empty() {
+        send.reserve_capacity(data.len());
+        let capacity = match std::future::poll_fn(|cx| send.poll_capacity(cx)).await {
+            Some(capacity) => capacity?,
+            None => Err(crate::Error::InternalServerError("HTTP/2 stream was closed".into()))?,
+        };
+        let chunk = data.split_to(capacity.min(data.len()));
+        send.send_data(chunk, end_of_stream && data.is_empty())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{read_preface, read_request, write_response, Rewind, PREFACE};
+    use crate::application::http::route::{HttpBody, IntoResponse, Request, Response};
+
+    /// Sends a request from an `h2` client over an in-memory h2c connection, and answers it with `response`
+    /// (or the error from reading the request). Returns the request as the handler would have seen it.
+    async fn round_trip(
+        request: http::Request<()>,
+        body: &'static [u8],
+        max_length: usize,
+        response: Response,
+    ) -> (Option<Request>, http::response::Parts, Vec<u8>) {
+        let (client_io, mut server_io) = tokio::io::duplex(64 * 1024);
+        let server = tokio::spawn(async move {
+            let preface = read_preface(&mut server_io).await.unwrap();
+            assert_eq!(preface, PREFACE);
+            let mut connection =
+                h2::server::handshake(Rewind::new(preface, server_io)).await.unwrap();
+            let (request, respond) = connection.accept().await.unwrap().unwrap();
+            let handled = tokio::spawn(async move {
+                let (request, response) = match read_request(request, max_length).await {
+                    Ok(request) => (Some(request), response),
+                    Err(err) => (None, err.into_response()),
+                };
+                write_response(response, respond, false).await.unwrap();
+                request
+            });
+            // The connection has to keep being driven for the stream's frames to go anywhere.
+            while let Some(Ok(_)) = connection.accept().await {}
+            handled.await.unwrap()
+        });
+
+        let (mut client, connection) = h2::client::handshake(client_io).await.unwrap();
+        tokio::spawn(connection);
+        let (response, mut send) = client.send_request(request, body.is_empty()).unwrap();
+        if !body.is_empty() {
+            send.send_data(Bytes::from_static(body), true).unwrap();
+        }
+        let (parts, mut recv) = response.await.unwrap().into_parts();
+        let mut received = Vec::new();
+        while let Some(chunk) = recv.data().await {
+            let chunk = chunk.unwrap();
+            recv.flow_control().release_capacity(chunk.len()).unwrap();
+            received.extend_from_slice(&chunk);
+        }
+        drop(client);
+
+        (server.await.unwrap(), parts, received)
+    }
+
+    #[tokio::test]
+    async fn round_trips_an_h2c_request() {
+        let request = http::Request::post("http://example.com/events?page=2")
+            .header("cookie", "theme=dark")
+            .header("cookie", "lang=en")
+            .header("content-type", "text/plain")
+            .body(())
+            .unwrap();
+        let response = Response::ok()
+            .with_body(b"done".to_vec())
+            .with_header("Connection", "keep-alive")
+            .with_header("X-Custom", "yes");
+
+        let (request, response, body) = round_trip(request, b"hello", 1024, response).await;
+        let request = request.expect("The request should have been read");
+        assert_eq!(request.path, "/events");
+        assert_eq!(request.query, "page=2");
+        assert_eq!(
+            request.headers.get("cookie").map(|cookie| cookie.as_str()),
+            Some("theme=dark; lang=en")
+        );
+        assert_eq!(request.headers.host(), Some("example.com"));
+        assert!(matches!(request.body, HttpBody::Bytes(ref bytes) if bytes == b"hello"));
+
+        // The client would have reset the stream if a connection header had been sent.
+        assert_eq!(response.status, 200);
+        assert!(response.headers.get("connection").is_none());
+        assert_eq!(response.headers.get("x-custom").unwrap(), "yes");
+        assert_eq!(body, b"done");
+    }
+
+    #[tokio::test]
+    async fn rejects_bodies_over_the_max_length() {
+        let request = http::Request::post("http://example.com/upload").body(()).unwrap();
+        let (request, response, _) = round_trip(request, b"too long", 4, Response::ok()).await;
+        assert!(request.is_none());
+        assert_eq!(response.status, 413);
+    }
+
+    #[tokio::test]
+    async fn reads_a_preface_split_across_writes() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(&PREFACE[..10]).await.unwrap();
+        let reading = tokio::spawn(async move { read_preface(&mut server).await.unwrap() });
+        tokio::task::yield_now().await;
+        client.write_all(&PREFACE[10..]).await.unwrap();
+        assert_eq!(reading.await.unwrap(), PREFACE);
+
+        // Anything else is handed back untouched.
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(b"PUT / HTTP/1.1\r\n\r\n").await.unwrap();
+        drop(client);
+        let read = read_preface(&mut server).await.unwrap();
+        let mut rewound = String::new();
+        Rewind::new(read, server).read_to_string(&mut rewound).await.unwrap();
+        assert_eq!(rewound, "PUT / HTTP/1.1\r\n\r\n");
+    }
+}
@@ -1,12 +1,17 @@
 use std::collections::HashSet;
-use std::io::Write;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::thread::JoinHandle;
-use std::{collections::HashMap, future::Future, net::TcpListener, pin::Pin};
+use std::time::Duration;
+use std::{collections::HashMap, future::Future, pin::Pin};
 
+use crate::application::http::cookies::{CookieKey, Key};
 use crate::application::http::into_route_handler::IntoRouteHandler;
+use crate::application::http::negotiation::Serializers;
+use crate::application::http::request_id::{sync_with_request_id, with_request_id};
+use crate::application::http::serializers::ContentSerializer;
 use crate::auth::gateway::{self, extract_session, AppUserCreateRequest, Session};
+use crate::errors::{sync_with_detail_policy, with_detail_policy, DetailPolicy};
 use crate::tasks::runner::{IntoTaskHandler, Signal, TaskExecutor};
 use env_logger::Env;
 use log;
@@ -30,19 +35,38 @@ use tailwag_orm::{
 };
 use tailwag_utils::time_exec;
 use tailwag_utils::types::generic_type_map::TypeInstanceMap;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::timeout;
 
 use crate::application::http::route::{IntoResponse, RequestContext, ServerContext};
 use crate::{auth::gateway::AppUser, traits::rest_api::BuildRoutes};
 
-use super::http::route::{Request, Response};
-use super::middleware::cors;
+use super::http::response_body::ResponseBody;
+use super::http::route::{HttpMethod, HttpVersion, Request, Response};
+use super::http::route_error::RouteError;
+use super::middleware::{compression, cors};
 use super::static_files::load_static;
-use super::{http::route::Route, stats::RunResult};
+use super::tls::TlsConfig;
+use super::{
+    http::route::Route,
+    stats::{RunResult, Statistics},
+};
+
+pub mod http2;
+mod panics;
 
 #[derive(thiserror::Error, Debug)]
 pub enum ApplicationError {
     #[error("Something went wrong.")]
     Error,
+    #[error("{} invalid route(s) configured:\n{}", .0.len(), one_per_line(.0))]
+    InvalidRoutes(Vec<RouteError>),
+}
+
+fn one_per_line(errors: &[RouteError]) -> String {
+    errors.iter().map(|err| format!("  - {}", err)).collect::<Vec<_>>().join("\n")
 }
 
 pub type Middleware = dyn Send
@@ -64,10 +88,14 @@ pub struct WebServiceConfig {
     application_name: String,
     socket_addr: String,
     _max_threads: usize,
+    /// How long a client gets to send a whole request, body included, in milliseconds (despite the name).
+    /// Uploads have to finish within it too, so raise `REQUEST_TIMEOUT_MS` when accepting large files.
     request_timeout_seconds: u64,
+    keep_alive_timeout_ms: u64,
     port: i32,
     migrate_on_init: bool,
     database_conn_string: String,
+    tls: Option<TlsConfig>,
 }
 // What if I do something like
 // ```rust
@@ -75,6 +103,9 @@ pub struct WebServiceConfig {
 // ````
 pub enum AdminActions {
     KillServer,
+    /// Re-reads the TLS certificate and key from disk. New connections use the new certificate, while
+    /// connections that are already open keep the one they were established with.
+    ReloadTlsCertificates,
 }
 
 type HandlerFn =
@@ -87,6 +118,7 @@ pub struct WebServiceInner {
     // routes: Arc<Route>,
     resources: UnconnectedDataSystem,
     server_data: Arc<TypeInstanceMap>,
+    statistics: Arc<Statistics>,
 }
 
 #[derive(tailwag_macros::Deref)]
@@ -94,7 +126,7 @@ pub struct WebService {
     #[deref]
     inner: WebServiceInner,
     task_executor: Option<TaskExecutor>,
-    admin_rx: Receiver<AdminActions>,
+    admin_rx: UnboundedReceiver<AdminActions>,
 }
 
 // TODO: Separate definition from config
@@ -147,6 +179,11 @@ impl Default for WebServiceBuilder {
                 .map_or(30000, |val| val
                     .parse()
                     .expect("REQUEST_TIMEOUT_SECONDS must be a valid integer.")));
+        let keep_alive_timeout_ms = std::env::var("KEEP_ALIVE_TIMEOUT_MS") // Defaults to 5 seconds
+            .map_or(5000, |val| {
+                val.parse().expect("KEEP_ALIVE_TIMEOUT_MS must be a valid integer.")
+            });
+        log::debug!("KEEP_ALIVE_TIMEOUT_MS: {}", keep_alive_timeout_ms);
 
         let allowed_domains: HashSet<String> = std::env::var("ALLOWED_DOMAINS")
             .unwrap_or("localhost,127.0.0.1".into())
@@ -169,6 +206,8 @@ impl Default for WebServiceBuilder {
                 migrate_on_init,
                 database_conn_string,
                 request_timeout_seconds,
+                keep_alive_timeout_ms,
+                tls: TlsConfig::from_env(),
             },
             resources: DataSystem::builder(),
             root_route: Route::default(),
@@ -212,10 +251,13 @@ impl WebServiceBuilder {
     build_route_method!(post:Post);
     build_route_method!(delete:Delete);
     build_route_method!(patch:Patch);
+    build_route_method!(put:Put);
     build_route_method!(get_public:Get);
     build_route_method!(post_public:Post);
     build_route_method!(delete_public:Delete);
     build_route_method!(patch_public:Patch);
+    build_route_method!(put_public:Put);
+    build_route_method!(put_protected:Put);
 }
 
 impl WebServiceBuilder {
@@ -226,10 +268,10 @@ impl WebServiceBuilder {
         builder
     }
 
-    // Adds an endpoint at `/static/{}`, which will serve the static content of all files in the `static` directory.
+    // Adds an endpoint at `/static/{}`, which will serve the static content of all files (and subdirectories) in the `static` directory.
     pub fn with_static_files(self) -> Self {
         // TODO: Move this to its own module
-        self.get("/static/{path}", load_static)
+        self.get("/static/{*path}", load_static)
     }
 
     // Adds the CRUD endpoints for the specified type, `T`. The routes that get created are determined by `T`'s implementation of the trait  `BuidlRoutes`.
@@ -301,6 +343,50 @@ impl WebServiceBuilder {
         self
     }
 
+    /// Terminates TLS in the service itself, instead of relying on a reverse proxy.
+    /// Overrides any TLS configuration from the environment.
+    pub fn with_tls(
+        mut self,
+        tls: TlsConfig,
+    ) -> Self {
+        self.config.tls = Some(tls);
+        self
+    }
+
+    /// Registers a serializer for content negotiation, alongside the built-in ones (JSON, CBOR, etc.).
+    pub fn with_serializer(
+        mut self,
+        serializer: impl ContentSerializer + 'static,
+    ) -> Self {
+        let serializers = self.server_data.get::<Serializers>().cloned().unwrap_or_default();
+        self.server_data.insert(serializers.with(serializer));
+        self
+    }
+
+    /// Sets the key used for signed and private cookies, instead of deriving it from `COOKIE_SECRET`.
+    pub fn with_cookie_key(
+        mut self,
+        key: Key,
+    ) -> Self {
+        self.server_data.insert(CookieKey::new(key));
+        self
+    }
+
+    /// Chooses which errors show their detail to clients, e.g. to hide `BadRequest` messages in release
+    /// builds too. Defaults to [default_detail_policy](crate::errors::default_detail_policy).
+    ///
+    /// ```ignore
+    /// WebService::builder("My Service")
+    ///     .with_error_details(|err| matches!(err, Error::InvalidField { .. }))
+    /// ```
+    pub fn with_error_details(
+        mut self,
+        policy: fn(&crate::Error) -> bool,
+    ) -> Self {
+        self.server_data.insert(DetailPolicy(policy));
+        self
+    }
+
     pub fn with_server_data<T: Clone + Send + Sync + 'static>(
         mut self,
         data: T,
@@ -309,11 +395,18 @@ impl WebServiceBuilder {
         self
     }
 
-    pub fn build_service(self) -> WebServiceBuildResponse {
-        let (admin_tx, admin_rx) = channel();
+    /// Builds the service. Fails if any of the configured routes were invalid or conflicting.
+    pub fn build_service(self) -> Result<WebServiceBuildResponse, ApplicationError> {
+        if !self.root_route.errors().is_empty() {
+            return Err(ApplicationError::InvalidRoutes(self.root_route.errors().to_vec()));
+        }
+        let (admin_tx, admin_rx) = unbounded_channel();
         // let WebServiceBuilder { config, root_route, migrations, forms, middleware_before, middleware_after, resources, server_data, task_executor } = self;
         let mut server_data = self.server_data;
         server_data.insert(self.task_executor.scheduler());
+        if server_data.get::<CookieKey>().is_none() {
+            server_data.insert(CookieKey::from_env());
+        }
 
         fn build_middleware(
             routes: Route,
@@ -353,15 +446,16 @@ impl WebServiceBuilder {
                 // routes: Arc::new(self.root_route), // No longer stored in Webservice - it's now moved to Middleware when running.
                 server_data: Arc::new(server_data),
                 consolidated_handler: build_middleware(self.root_route, self._exp_middleware),
+                statistics: Default::default(),
             },
             admin_rx,
             task_executor: Some(self.task_executor),
         };
 
-        WebServiceBuildResponse {
+        Ok(WebServiceBuildResponse {
             service,
             sender: admin_tx,
-        }
+        })
     }
 }
 
@@ -377,13 +471,19 @@ impl WebServiceBuilder {
     pub fn with_cors(self) -> Self {
         self.with_middleware(cors::handle_cors)
     }
+
+    /// Compresses responses (gzip, deflate, or brotli, as negotiated with `Accept-Encoding`), and
+    /// decodes compressed request bodies.
+    pub fn with_compression(self) -> Self {
+        self.with_middleware(compression::handle_compression)
+    }
 }
 
 #[derive(Deref)]
 pub struct WebServiceBuildResponse {
     #[deref]
     pub service: WebService,
-    pub sender: Sender<AdminActions>,
+    pub sender: UnboundedSender<AdminActions>,
 }
 
 impl WebServiceBuildResponse {
@@ -446,34 +546,94 @@ impl WebService {
     }
 
     async fn start_service(
-        self,
+        mut self,
         context: ServerContext,
     ) -> Result<RunResult, crate::Error> {
         let bind_addr = format!("{}:{}", &self.config.socket_addr, self.config.port);
-        log::info!("Starting service on {}", &bind_addr);
-        let listener = TcpListener::bind(&bind_addr).unwrap();
+        let tls_config = self.config.tls.clone();
+        let handshake_timeout = Duration::from_millis(self.config.request_timeout_seconds);
+        let mut tls_acceptor = tls_config.as_ref().map(TlsConfig::load_acceptor).transpose()?;
+        log::info!(
+            "Starting service on {} ({})",
+            &bind_addr,
+            if tls_acceptor.is_some() { "https" } else { "http" }
+        );
+        let listener = TcpListener::bind(&bind_addr).await?;
+        // Once every sender is dropped (e.g. when the service is started through `WebServiceBuildResponse::run`),
+        // there is nobody left to send admin actions, so we stop listening for them.
+        let mut admin_channel_open = true;
         log::info!("Waiting for connection....");
-        while let Ok((mut stream, _addr)) = listener.accept() {
-            if let Ok(AdminActions::KillServer) = self.admin_rx.try_recv() {
-                // If we've gotten a kill signal, then stop the server.
-                break;
+        loop {
+            tokio::select! {
+                action = self.admin_rx.recv(), if admin_channel_open => match action {
+                    Some(AdminActions::KillServer) => {
+                        // If we've gotten a kill signal, then stop the server.
+                        log::info!("Received kill signal, shutting down.");
+                        break;
+                    },
+                    Some(AdminActions::ReloadTlsCertificates) => match &tls_config {
+                        Some(tls_config) => match tls_config.load_acceptor() {
+                            Ok(acceptor) => {
+                                log::info!("Reloaded TLS certificates.");
+                                tls_acceptor = Some(acceptor);
+                            },
+                            // Keep serving with the old certificates, rather than taking the service down.
+                            Err(err) => log::error!("Failed to reload TLS certificates: {:?}", err),
+                        },
+                        None => log::warn!("Received TLS reload, but TLS is not configured."),
+                    },
+                    None => admin_channel_open = false,
+                },
+                connection = listener.accept() => {
+                    let (stream, addr) = match connection {
+                        Ok(connection) => connection,
+                        Err(err) => {
+                            log::error!("Failed to accept connection: {:?}", err);
+                            continue;
+                        },
+                    };
+                    log::info!("Received connection from {}!", addr.ip());
+                    // TODO: Rate-limiting & failtoban to block malicious actors
+
+                    let inner = self.inner.clone();
+                    let context = context.clone();
+                    match tls_acceptor.clone() {
+                        Some(acceptor) => {
+                            // The handshake happens on the connection's own task, so slow clients can't hold up the accept loop.
+                            tokio::spawn(async move {
+                                match timeout(handshake_timeout, acceptor.accept(stream)).await {
+                                    Ok(Ok(stream)) => {
+                                        let (_, session) = stream.get_ref();
+                                        if session.alpn_protocol() == Some(http2::ALPN_H2) {
+                                            inner.serve_http2(stream, addr, context).await
+                                        } else {
+                                            inner.handle_request(stream, addr, context).await
+                                        }
+                                    },
+                                    Ok(Err(err)) => {
+                                        log::warn!("TLS handshake with {} failed: {:?}", addr, err);
+                                        Ok(())
+                                    },
+                                    Err(_elapsed) => {
+                                        log::warn!("TLS handshake with {} timed out", addr);
+                                        Ok(())
+                                    },
+                                }
+                            });
+                        },
+                        None => {
+                            tokio::spawn(inner.handle_request(stream, addr, context));
+                        },
+                    }
+
+                    log::info!("Waiting for connection....");
+                },
             }
-
-            log::info!("Received connection from {}!", _addr.ip());
-            let _ = stream.set_read_timeout(Some(std::time::Duration::from_millis(
-                self.config.request_timeout_seconds,
-            )));
-            let _ = stream.set_write_timeout(Some(std::time::Duration::from_millis(
-                self.config.request_timeout_seconds,
-            )));
-            // TODO: Rate-limiting & failtoban to block malicious actors
-
-            let inner = self.inner.clone();
-            tokio::spawn(inner.handle_request(stream, context.clone()));
-
-            log::info!("Waiting for connection....");
         }
-        Ok(RunResult::default())
+        Ok(RunResult {
+            statistics: self.statistics.clone(),
+            ..Default::default()
+        })
     }
 
     fn start_task_executor(
@@ -516,18 +676,17 @@ impl WebService {
             }
         }
 
-        let mut task_scheduler = self
-            .task_executor
-            .as_ref()
-            .map(|te| te.scheduler())
-            .ok_or("Unable to get task scheduler.".to_string())?;
+        let mut task_scheduler =
+            self.task_executor.as_ref().map(|te| te.scheduler()).ok_or_else(|| {
+                crate::Error::InternalServerError("Unable to get task scheduler.".into())
+            })?;
         let tasks_thread = self.start_task_executor(context.clone());
         let result = self.start_service(context.clone()).await;
 
         // Let the tasks_thread die
-        task_scheduler
-            .enqueue(Signal::Kill)
-            .map_err(|err| format!("Unable to schedule task: {:?}", err))?;
+        task_scheduler.enqueue(Signal::Kill).map_err(|err| {
+            crate::Error::InternalServerError(format!("Unable to schedule task: {:?}", err))
+        })?;
         tasks_thread.map(|thread| thread.join());
         result
     }
@@ -536,32 +695,170 @@ impl WebService {
 /// This mod adds QueuedTask support to the WebApplication, running in a separate thread.
 /// #[cfg(feature = "tasks")]
 impl WebServiceInner {
-    pub async fn handle_request(
+    /// Runs the middleware and route handler for a request. A panic in either is logged (with the request
+    /// id and a backtrace) and answered with a `500`, instead of resetting the connection.
+    async fn call_handler(
+        &self,
+        request: Request,
+        context: RequestContext,
+        request_id: uuid::Uuid,
+    ) -> Response {
+        self.statistics.record_request();
+        let handler = self.consolidated_handler.clone();
+        let detail_policy = context.server_data.get::<DetailPolicy>().copied().unwrap_or_default();
+        let handler = with_detail_policy(detail_policy, handler(request, context));
+        match panics::catch_panic(with_request_id(request_id, handler)).await {
+            Ok(response) => response,
+            Err(_panic) => {
+                self.statistics.record_panic();
+                self.error_response(
+                    crate::Error::InternalServerError("The request handler panicked.".into()),
+                    request_id,
+                )
+            },
+        }
+    }
+
+    /// Renders an error that happened outside of a handler, e.g. while reading the request.
+    pub(crate) fn error_response(
+        &self,
+        err: crate::Error,
+        request_id: uuid::Uuid,
+    ) -> Response {
+        let detail_policy = self.server_data.get::<DetailPolicy>().copied().unwrap_or_default();
+        sync_with_request_id(request_id, || {
+            sync_with_detail_policy(detail_policy, || err.into_response())
+        })
+    }
+
+    /// Counters for the requests handled so far.
+    pub fn statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
+    /// Serves every request sent over a single connection. Connections are kept alive (HTTP/1.1 persistent
+    /// connections) until the client asks to close it, or sits idle for longer than the configured
+    /// `keep_alive_timeout_ms`. Pipelined requests are read off the same buffer and answered in order.
+    pub async fn handle_request<S>(
         self,
-        mut stream: std::net::TcpStream,
+        stream: S,
+        peer_addr: SocketAddr,
         server_context: ServerContext,
-    ) -> Result<RequestMetrics, crate::Error> {
-        let request_id = uuid::Uuid::new_v4();
-        log::info!("[REQ_ID {request_id}] Connection received from {}", stream.peer_addr()?);
-
-        let request = time_exec!(
-            "[REQ_ID {request_id}] Request Destructuring",
-            crate::application::http::route::Request::try_from(&stream)
-        );
-        let response = match request {
-            Ok(request) => {
-                let context = time_exec!(
-                    "[REQ_ID {request_id}] Build Context",
-                    RequestContext::from_server_context(server_context)
-                );
-
-                let handler = self.consolidated_handler.clone();
-                handler(request, context).await
+    ) -> Result<RequestMetrics, crate::Error>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        log::info!("Connection received from {}", peer_addr);
+        let idle_timeout = Duration::from_millis(self.config.keep_alive_timeout_ms);
+        let request_timeout = Duration::from_millis(self.config.request_timeout_seconds);
+
+        // HTTP/2 with prior knowledge (h2c) opens with the connection preface instead of a request line.
+        // Whatever was read while looking for it is put back in front of the stream afterwards.
+        let mut stream = stream;
+        let preface = match timeout(idle_timeout, http2::read_preface(&mut stream)).await {
+            Ok(preface) => preface?,
+            Err(_elapsed) => {
+                log::debug!("Closing idle connection from {}", peer_addr);
+                stream.shutdown().await.ok();
+                return Ok(());
             },
-            Err(err) => err.into_response(),
         };
+        let is_h2c = preface == http2::PREFACE;
+        let stream = http2::Rewind::new(preface, stream);
+        if is_h2c {
+            return self.serve_http2(stream, peer_addr, server_context).await;
+        }
 
-        stream.write_all(&response.as_bytes())?;
+        // Reads and writes both go through the `BufReader`, which passes writes straight to the stream.
+        let mut stream = BufReader::new(stream);
+        loop {
+            // Wait (up to the idle timeout) for the next request to show up, unless a pipelined request is already buffered.
+            if stream.buffer().is_empty() {
+                match timeout(idle_timeout, stream.fill_buf()).await {
+                    Ok(Ok(buf)) if buf.is_empty() => break, // Client closed the connection
+                    Ok(Ok(_)) => (),
+                    Ok(Err(err)) => Err(err)?,
+                    Err(_elapsed) => {
+                        log::debug!("Closing idle connection from {}", peer_addr);
+                        break;
+                    },
+                }
+            }
+
+            let request_id = uuid::Uuid::new_v4();
+            // The timeout covers the body too, including multipart uploads that are streamed to disk.
+            let request = time_exec!(
+                "[REQ_ID {request_id}] Request Destructuring",
+                timeout(request_timeout, Request::read_next(&mut stream)).await
+            );
+            let mut is_head = false;
+            let mut client_version = HttpVersion::V1_1;
+            let (response, keep_alive) = match request {
+                Ok(Ok(Some(request))) => {
+                    is_head = request.method == HttpMethod::Head;
+                    client_version = request.http_version.clone();
+                    let keep_alive = request.keep_alive();
+                    let context = time_exec!(
+                        "[REQ_ID {request_id}] Build Context",
+                        RequestContext::from_server_context(server_context.clone())
+                    );
+
+                    let response = self.call_handler(request, context, request_id).await;
+                    // HTTP/1.0 can't chunk a stream, so closing the connection is the only way to end it.
+                    let keep_alive = keep_alive
+                        && !(client_version == HttpVersion::V1_0
+                            && matches!(response.body, ResponseBody::Stream(_)));
+                    let response = match (keep_alive, &client_version) {
+                        (false, _) => response.with_header("Connection", "close"),
+                        (true, HttpVersion::V1_0) => {
+                            response.with_header("Connection", "keep-alive").with_header(
+                                "Keep-Alive",
+                                format!("timeout={}", idle_timeout.as_secs()),
+                            )
+                        },
+                        (true, _) => response,
+                    };
+                    (response, keep_alive)
+                },
+                Ok(Ok(None)) => break,
+                // We can't tell where the next request starts after a malformed one, so the connection is closed.
+                Ok(Err(err)) => {
+                    (self.error_response(err, request_id).with_header("Connection", "close"), false)
+                },
+                Err(_elapsed) => {
+                    log::warn!(
+                        "[REQ_ID {request_id}] Timed out waiting for request from {}",
+                        peer_addr
+                    );
+                    break;
+                },
+            };
+
+            // Streaming bodies may legitimately take longer than the request timeout to produce.
+            let is_streaming = !is_head && matches!(response.body, ResponseBody::Stream(_));
+            let write = async {
+                if is_head {
+                    response.write_head_to(&client_version, &mut stream).await
+                } else {
+                    response.write_to(&client_version, &mut stream).await
+                }
+            };
+            let written = if is_streaming {
+                write.await
+            } else {
+                timeout(request_timeout, write)
+                    .await
+                    .unwrap_or_else(|_elapsed| Err(std::io::ErrorKind::TimedOut.into()))
+            };
+            if let Err(err) = written {
+                log::warn!("[REQ_ID {request_id}] Failed to write response: {:?}", err);
+                break;
+            }
+            if !keep_alive {
+                break;
+            }
+        }
+        stream.shutdown().await.ok();
 
         Ok(())
     }
@@ -0,0 +1,62 @@
+use std::{any::Any, backtrace::Backtrace, future::Future, panic::AssertUnwindSafe, sync::Once};
+
+use futures::FutureExt;
+
+use crate::application::http::request_id::current_request_id;
+
+static INSTALL_HOOK: Once = Once::new();
+
+/// Logs panics that happen while a request is handled, along with the request id and a backtrace.
+/// Panics anywhere else are left to the hook that was already installed.
+fn install_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| match current_request_id() {
+            Some(request_id) => {
+                let location = info.location().map(|l| l.to_string()).unwrap_or_default();
+                log::error!(
+                    "[REQ_ID {request_id}] Handler panicked at {}: {}\n{}",
+                    location,
+                    panic_message(info.payload()),
+                    Backtrace::force_capture()
+                );
+            },
+            None => previous_hook(info),
+        }));
+    });
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>")
+}
+
+/// Runs a request's handler, catching a panic instead of letting it kill the connection's task.
+/// The panic is logged by the hook, so only its message is returned.
+pub(crate) async fn catch_panic<F: Future>(handler: F) -> Result<F::Output, String> {
+    install_hook();
+    AssertUnwindSafe(handler)
+        .catch_unwind()
+        .await
+        .map_err(|payload| panic_message(payload.as_ref()).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::catch_panic;
+
+    #[tokio::test]
+    async fn catches_panics_in_handlers() {
+        assert_eq!(catch_panic(async { 42 }).await, Ok(42));
+        let result = catch_panic(async {
+            if true {
+                panic!("Handler failed: {}", 42);
+            }
+        })
+        .await;
+        assert_eq!(result, Err("Handler failed: 42".to_string()));
+    }
+}
@@ -4,7 +4,10 @@ use tailwag_orm::{
     queries::filterable_types::FilterEq,
 };
 
-use crate::application::http::route::RoutePolicy;
+use crate::application::http::{
+    cookies::{self, CookieKey, Cookies},
+    route::RoutePolicy,
+};
 use argon2::{
     password_hash::{rand_core::OsRng, SaltString},
     Argon2, PasswordHasher, PasswordVerifier,
@@ -166,29 +169,27 @@ pub fn extract_session(
 
         // First, log request:
         log::debug!("{:?} {:?} {:?}", &request.method, &request.path, request.headers);
-        fn extract_authz_token(request: &Request) -> Option<String> {
+        let Some(cookie_key) = context.server_data.get::<CookieKey>().cloned() else {
+            return Response::internal_server_error();
+        };
+        fn extract_authz_token(
+            request: &Request,
+            cookie_key: CookieKey,
+        ) -> Option<String> {
             if let Some(header) = request
                 .headers
                 .get("Authorization")
                 .and_then(|header| header.as_str().strip_prefix("Bearer "))
             {
                 Some(header.to_owned())
-            } else if let Some(cookie) =
-                request.headers.get("Cookie").map(|header| header.as_str().to_string())
-            {
-                let session_cookie = dbg!(cookie)
-                    .split(';')
-                    .map(|cookie| cookie.trim())
-                    .find(|cookie| cookie.starts_with("_id"))
-                    .and_then(|cookie| cookie.split('=').last())
-                    .map(|cookie| cookie.trim().into());
-                session_cookie
             } else {
-                None
+                Cookies::from_headers(&request.headers, cookie_key)
+                    .value(SESSION_COOKIE)
+                    .map(|cookie| cookie.to_owned())
             }
         }
 
-        let session_id = extract_authz_token(&request)
+        let session_id = extract_authz_token(&request, cookie_key)
             .and_then(|token| {
                 jsonwebtoken::decode::<JwtClaims>(
                     &token,
@@ -232,6 +233,7 @@ pub struct LoginRequest {
 
 // TODO: Move to config
 const SESSION_LENGTH_MS: u64 = 3600000;
+const SESSION_COOKIE: &str = "_id";
 
 #[derive(Serialize, Deserialize)]
 pub struct LoginResponse {
@@ -256,13 +258,16 @@ pub async fn login(
         .pop()
         .ok_or_else(|| {
             // TODO: Protect against authn timing attacks, by verifying the password against a dummy hash, and writing a dummy session to the store.
-            crate::Error::NotFound
+            crate::Error::Unauthorized
         })?;
 
-    argon2::Argon2::default().verify_password(
-        creds.password.as_bytes(),
-        &argon2::PasswordHash::new(&account.passhash).unwrap(),
-    )?;
+    argon2::Argon2::default()
+        .verify_password(
+            creds.password.as_bytes(),
+            &argon2::PasswordHash::new(&account.passhash).unwrap(),
+        )
+        // Same as an unknown account, so that the response doesn't reveal which accounts exist.
+        .map_err(|_| crate::Error::Unauthorized)?;
     let account = AppUser {
         passhash: "".into(),
         // roles: vec![AuthorizationRole::Admin],
@@ -293,12 +298,10 @@ pub async fn login(
         access: jwt.clone(),
         refresh: "".into(),
     };
-    let _cookie_header_val = format!(
-        "_id={}; HttpOnly; SameSite=None",
-        // "_id={}; HttpOnly; Domain={}; Path={}",
-        jwt,
-    );
-    let response = response.into_response().with_header("Set-Cookie", _cookie_header_val);
+    let session_cookie = cookies::build(SESSION_COOKIE, jwt)
+        .same_site(cookies::SameSite::None)
+        .max_age(cookies::Duration::milliseconds(SESSION_LENGTH_MS as i64));
+    let response = response.into_response().with_cookie(session_cookie);
     Ok(response)
 }
 
@@ -310,7 +313,7 @@ pub async fn logout(
     if let Some(session) = ctx.get_request_data::<Session>() {
         sessions.delete(session.clone()).await.ok();
     }
-    Response::ok()
+    Response::ok().without_cookie(cookies::build(SESSION_COOKIE, ""))
 }
 
 #[derive(Serialize, Deserialize)]
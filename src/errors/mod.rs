@@ -1 +1,592 @@
+use std::{fmt::Display, future::Future, io::ErrorKind};
 
+use serde::{Deserialize, Deserializer, Serialize};
+use uuid::Uuid;
+
+use crate::application::http::{
+    request_id::current_request_id,
+    route::{HttpStatus, IntoResponse, Response},
+};
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Details are only shown for every error in development builds. A `release` build always hides them
+/// where [default_detail_policy] says so, even if `development` is also enabled.
+pub(crate) const EXPOSE_ALL_DETAILS: bool =
+    cfg!(all(feature = "development", not(feature = "release")));
+
+/// Decides whether an error's detail is shown to clients. Each service keeps its own in its server data,
+/// set with [WebServiceBuilder::with_error_details](crate::application::WebServiceBuilder::with_error_details).
+#[derive(Clone, Copy)]
+pub struct DetailPolicy(pub fn(&crate::Error) -> bool);
+
+impl Default for DetailPolicy {
+    fn default() -> Self {
+        DetailPolicy(default_detail_policy)
+    }
+}
+
+impl DetailPolicy {
+    pub fn exposes(
+        &self,
+        error: &crate::Error,
+    ) -> bool {
+        (self.0)(error)
+    }
+}
+
+tokio::task_local! {
+    static DETAIL_POLICY: DetailPolicy;
+}
+
+/// Client errors describe what was wrong with the request, but server errors can leak internals (queries,
+/// file paths, etc.), so those are only exposed in development builds.
+pub fn default_detail_policy(error: &crate::Error) -> bool {
+    match error {
+        crate::Error::BadRequest(_)
+        | crate::Error::InvalidField {
+            ..
+        } => true,
+        crate::Error::InternalServerError(_)
+        | crate::Error::TaskSchedculingError(_)
+        | crate::Error::ServiceUnavailable(_) => EXPOSE_ALL_DETAILS,
+        crate::Error::Custom(err) => err.exposes_detail(),
+        crate::Error::Unauthorized
+        | crate::Error::Conflict
+        | crate::Error::NotFound
+        | crate::Error::EntityTooLarge
+        | crate::Error::UnsupportedMediaType
+        | crate::Error::NotAcceptable => true,
+    }
+}
+
+/// The policy of the service handling the current request. Outside of a request, the default one.
+pub(crate) fn current_detail_policy() -> DetailPolicy {
+    DETAIL_POLICY.try_with(|policy| *policy).unwrap_or_default()
+}
+
+/// Runs a request's handler with its service's policy in scope, for any errors it turns into responses.
+pub(crate) async fn with_detail_policy<F: Future>(
+    policy: DetailPolicy,
+    handler: F,
+) -> F::Output {
+    DETAIL_POLICY.scope(policy, handler).await
+}
+
+/// Like [with_detail_policy], for work that doesn't need to be awaited.
+pub(crate) fn sync_with_detail_policy<T>(
+    policy: DetailPolicy,
+    f: impl FnOnce() -> T,
+) -> T {
+    DETAIL_POLICY.sync_scope(policy, f)
+}
+
+/// A machine-readable error body, sent as `application/problem+json`. Ref: RFC 9457 (which obsoletes RFC 7807)
+#[derive(Debug, Clone)]
+pub struct ProblemDetails {
+    /// A URI identifying the kind of problem. `about:blank` means the status code says it all.
+    pub problem_type: String,
+    pub title: String,
+    pub status: HttpStatus,
+    pub detail: Option<String>,
+    /// Ties the response to the server's logs for the same request.
+    pub request_id: Option<Uuid>,
+    /// The parts of the request that couldn't be read, e.g. a body field with the wrong type.
+    pub invalid_params: Vec<InvalidParam>,
+}
+
+/// A field or parameter that was rejected. `name` is its path within the request, e.g. `events[2].start`.
+/// Ref: RFC 9457 3 (the `invalid-params` example extension)
+#[derive(Debug, Clone, Serialize)]
+pub struct InvalidParam {
+    pub name: String,
+    pub reason: String,
+}
+
+/// The serialized form of [ProblemDetails]. Kept separate, since anything `Serialize` is already
+/// turned into a response through content negotiation.
+#[derive(Serialize)]
+struct ProblemBody<'a> {
+    #[serde(rename = "type")]
+    problem_type: &'a str,
+    title: &'a str,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    invalid_params: &'a [InvalidParam],
+}
+
+impl ProblemDetails {
+    pub fn new(status: HttpStatus) -> Self {
+        Self {
+            problem_type: "about:blank".into(),
+            title: status.reason_phrase().into(),
+            status,
+            detail: None,
+            request_id: current_request_id(),
+            invalid_params: Vec::new(),
+        }
+    }
+
+    pub fn with_type(
+        mut self,
+        problem_type: impl Into<String>,
+        title: impl Into<String>,
+    ) -> Self {
+        self.problem_type = problem_type.into();
+        self.title = title.into();
+        self
+    }
+
+    pub fn with_detail(
+        mut self,
+        detail: impl Into<String>,
+    ) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_invalid_param(
+        mut self,
+        name: impl Into<String>,
+        reason: impl Into<String>,
+    ) -> Self {
+        self.invalid_params.push(InvalidParam {
+            name: name.into(),
+            reason: reason.into(),
+        });
+        self
+    }
+}
+
+impl IntoResponse for ProblemDetails {
+    fn into_response(self) -> Response {
+        let body = ProblemBody {
+            problem_type: &self.problem_type,
+            title: &self.title,
+            status: self.status.clone() as usize as u16,
+            detail: self.detail.as_deref(),
+            request_id: self.request_id,
+            invalid_params: &self.invalid_params,
+        };
+        let body = serde_json::to_vec(&body).unwrap_or_default();
+        Response::ok()
+            .with_status(self.status.clone())
+            .with_body(body)
+            .with_header("Content-Type", PROBLEM_JSON)
+    }
+}
+
+/// Deserializes `T`, keeping track of where it failed (e.g. `events[2].start`), so the client can be told
+/// which field was wrong. Errors that aren't about any one field are prefixed with `context`.
+pub(crate) fn deserialize_tracked<'de, D, T>(
+    deserializer: D,
+    context: &str,
+) -> Result<T, crate::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    serde_path_to_error::deserialize(deserializer).map_err(|err| invalid_field(err, context))
+}
+
+pub(crate) fn invalid_field<E: Display>(
+    err: serde_path_to_error::Error<E>,
+    context: &str,
+) -> crate::Error {
+    let field = err.path().to_string();
+    let reason = err.into_inner().to_string();
+    // The root of the value is shown as `.`
+    if field == "." {
+        crate::Error::BadRequest(format!("{}: {}", context, reason))
+    } else {
+        crate::Error::InvalidField {
+            field,
+            reason,
+        }
+    }
+}
+
+/// Lets error types from other crates choose their own response, and be returned from handlers with `?`.
+///
+/// ```ignore
+/// #[derive(Debug)]
+/// struct PaymentDeclined(String);
+///
+/// impl ResponseError for PaymentDeclined {
+///     fn status(&self) -> HttpStatus {
+///         HttpStatus::Conflict
+///     }
+///     fn detail(&self) -> Option<String> {
+///         Some(self.0.clone())
+///     }
+/// }
+/// ```
+pub trait ResponseError: std::fmt::Debug + Send + Sync + 'static {
+    fn status(&self) -> HttpStatus {
+        HttpStatus::InternalServerError
+    }
+
+    /// Shown to the client as the problem's `detail`.
+    fn detail(&self) -> Option<String> {
+        None
+    }
+
+    /// Whether the detail can be shown to clients. Like the crate's own errors, only client errors show it
+    /// in release builds.
+    fn exposes_detail(&self) -> bool {
+        (self.status() as usize) < 500 || EXPOSE_ALL_DETAILS
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    /// A problem details response built from the above. Override it to send a different body. It's only
+    /// used when the detail is exposed; otherwise clients get a bare problem with the status and headers.
+    fn error_response(&self) -> Response {
+        let problem = ProblemDetails::new(self.status());
+        let problem = match self.detail() {
+            Some(detail) => problem.with_detail(detail),
+            None => problem,
+        };
+        self.headers().into_iter().fold(problem.into_response(), |response, (name, value)| {
+            response.with_appended_header(name, value)
+        })
+    }
+}
+
+impl<E: ResponseError> From<E> for crate::Error {
+    fn from(value: E) -> Self {
+        crate::Error::Custom(Box::new(value))
+    }
+}
+
+impl From<&str> for crate::Error {
+    fn from(value: &str) -> Self {
+        crate::Error::BadRequest(value.into())
+    }
+}
+
+impl From<String> for crate::Error {
+    fn from(value: String) -> Self {
+        crate::Error::BadRequest(value)
+    }
+}
+
+/// A problem with the request body, found while decoding it behind an `AsyncRead` (e.g. a chunked body
+/// being read as multipart). Carried through `io::Error`, so that it still gets the right response.
+#[derive(Debug)]
+pub(crate) enum BodyError {
+    TooLarge,
+    Invalid(String),
+}
+
+impl Display for BodyError {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            BodyError::TooLarge => write!(f, "The request body is too large."),
+            BodyError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+impl From<BodyError> for std::io::Error {
+    fn from(value: BodyError) -> Self {
+        std::io::Error::other(value)
+    }
+}
+
+/// Malformed input shows up as invalid data or an early end of the stream. Anything else is on our end.
+impl From<std::io::Error> for crate::Error {
+    fn from(value: std::io::Error) -> Self {
+        match value.get_ref().and_then(|inner| inner.downcast_ref::<BodyError>()) {
+            Some(BodyError::TooLarge) => return crate::Error::EntityTooLarge,
+            Some(BodyError::Invalid(reason)) => return crate::Error::BadRequest(reason.clone()),
+            None => (),
+        }
+        match value.kind() {
+            // The error's own text describes our internals more than the request, so it's only logged.
+            ErrorKind::InvalidData | ErrorKind::InvalidInput | ErrorKind::UnexpectedEof => {
+                log::warn!("[BAD REQUEST] Failed to read the request: {}", value);
+                crate::Error::BadRequest("The request could not be read.".into())
+            },
+            _ => crate::Error::InternalServerError(value.to_string()),
+        }
+    }
+}
+
+macro_rules! bad_request_from {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl From<$ty> for crate::Error {
+                fn from(value: $ty) -> Self {
+                    crate::Error::BadRequest(value.to_string())
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! internal_server_error_from {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl From<$ty> for crate::Error {
+                fn from(value: $ty) -> Self {
+                    crate::Error::InternalServerError(value.to_string())
+                }
+            }
+        )*
+    };
+}
+
+// Request bodies and parameters that couldn't be read.
+bad_request_from!(
+    std::string::FromUtf8Error,
+    std::str::Utf8Error,
+    serde_json::Error,
+    serde_urlencoded::de::Error,
+    serde::de::value::Error,
+    erased_serde::Error,
+);
+
+internal_server_error_from!(
+    serde_urlencoded::ser::Error,
+    h2::Error,
+    http::Error,
+    crate::application::ApplicationError,
+);
+
+/// Ref: https://www.postgresql.org/docs/current/errcodes-appendix.html
+const UNIQUE_VIOLATION: &str = "23505";
+
+/// Missing rows are a 404, duplicates a 409, and an unreachable database a 503.
+fn database_error(
+    err: &sqlx::Error,
+    message: String,
+) -> crate::Error {
+    match err {
+        sqlx::Error::RowNotFound => crate::Error::NotFound,
+        sqlx::Error::Database(db_error) if db_error.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+            log::warn!("[CONFLICT] {}", message);
+            crate::Error::Conflict
+        },
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => crate::Error::ServiceUnavailable(message),
+        _ => crate::Error::InternalServerError(message),
+    }
+}
+
+impl From<sqlx::Error> for crate::Error {
+    fn from(value: sqlx::Error) -> Self {
+        database_error(&value, value.to_string())
+    }
+}
+
+/// ORM errors are mapped by the database error underneath them, if there is one.
+impl From<tailwag_orm::Error> for crate::Error {
+    fn from(value: tailwag_orm::Error) -> Self {
+        let mut source: Option<&(dyn std::error::Error + 'static)> = Some(&value);
+        while let Some(err) = source {
+            if let Some(sqlx_error) = err.downcast_ref::<sqlx::Error>() {
+                return database_error(sqlx_error, value.to_string());
+            }
+            source = err.source();
+        }
+        crate::Error::InternalServerError(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::application::http::{
+        request_id::sync_with_request_id,
+        response_body::ResponseBody,
+        route::{HttpStatus, IntoResponse},
+    };
+
+    fn problem(error: crate::Error) -> serde_json::Value {
+        let request_id = uuid::Uuid::nil();
+        let response = sync_with_request_id(request_id, || error.into_response());
+        assert_eq!(
+            response.headers.get("content-type").map(|ct| ct.as_str()),
+            Some(super::PROBLEM_JSON)
+        );
+        let ResponseBody::Bytes(body) = response.body else {
+            panic!("Problem details should be buffered");
+        };
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[test]
+    fn describes_errors_as_problem_details() {
+        let body = problem(crate::Error::BadRequest("Missing field `name`".into()));
+        assert_eq!(body["type"], "about:blank");
+        assert_eq!(body["title"], "Bad Request");
+        assert_eq!(body["status"], 400);
+        assert_eq!(body["detail"], "Missing field `name`");
+        assert_eq!(body["request_id"], uuid::Uuid::nil().to_string());
+
+        let body = problem(crate::Error::InternalServerError("connection refused".into()));
+        assert_eq!(body["detail"].is_string(), super::EXPOSE_ALL_DETAILS);
+
+        let invalid_data = std::io::Error::new(std::io::ErrorKind::InvalidData, "/srv/uploads/tmp");
+        let body = problem(invalid_data.into());
+        assert_eq!(body["detail"], "The request could not be read.");
+
+        let hide_everything = super::DetailPolicy(|_| false);
+        let body = super::sync_with_detail_policy(hide_everything, || {
+            problem(crate::Error::BadRequest("Missing field `name`".into()))
+        });
+        assert!(body.get("detail").is_none());
+    }
+
+    #[derive(Debug)]
+    struct LedgerUnavailable;
+
+    impl super::ResponseError for LedgerUnavailable {
+        fn detail(&self) -> Option<String> {
+            Some("ledger.internal:5432 refused the connection".into())
+        }
+    }
+
+    #[test]
+    fn hides_the_detail_of_custom_server_errors() {
+        let body = problem(crate::Error::from(PaymentDeclined));
+        assert_eq!(body["detail"], "Card declined");
+
+        let body = problem(crate::Error::from(LedgerUnavailable));
+        assert_eq!(body["status"], 500);
+        assert_eq!(body["detail"].is_string(), super::EXPOSE_ALL_DETAILS);
+    }
+
+    #[derive(Debug)]
+    struct PaymentDeclined;
+
+    impl super::ResponseError for PaymentDeclined {
+        fn status(&self) -> HttpStatus {
+            HttpStatus::Conflict
+        }
+        fn detail(&self) -> Option<String> {
+            Some("Card declined".into())
+        }
+        fn headers(&self) -> Vec<(String, String)> {
+            vec![("Retry-After".into(), "60".into())]
+        }
+    }
+
+    #[test]
+    fn maps_error_types_to_statuses() {
+        fn status(error: crate::Error) -> usize {
+            error.into_response().status as usize
+        }
+        let invalid_data = std::io::Error::new(std::io::ErrorKind::InvalidData, "not utf-8");
+        assert_eq!(status(invalid_data.into()), 400);
+        assert_eq!(status(std::io::Error::from(std::io::ErrorKind::PermissionDenied).into()), 500);
+        assert_eq!(status(sqlx::Error::RowNotFound.into()), 404);
+        assert_eq!(status(sqlx::Error::PoolTimedOut.into()), 503);
+
+        let response = crate::Error::from(PaymentDeclined).into_response();
+        assert_eq!(response.status as usize, 409);
+        assert_eq!(response.headers.get("retry-after").map(|h| h.as_str()), Some("60"));
+
+        let response = crate::Error::Unauthorized.into_response();
+        assert_eq!(response.status as usize, 401);
+        assert_eq!(response.headers.get("www-authenticate").map(|h| h.as_str()), Some("Bearer"));
+    }
+
+    /// A unique constraint violation, as Postgres reports it.
+    #[derive(Debug)]
+    struct UniqueViolation;
+
+    impl std::fmt::Display for UniqueViolation {
+        fn fmt(
+            &self,
+            f: &mut std::fmt::Formatter<'_>,
+        ) -> std::fmt::Result {
+            f.write_str("duplicate key value violates unique constraint")
+        }
+    }
+
+    impl std::error::Error for UniqueViolation {}
+
+    impl sqlx::error::DatabaseError for UniqueViolation {
+        fn message(&self) -> &str {
+            "duplicate key value violates unique constraint"
+        }
+        fn code(&self) -> Option<std::borrow::Cow<'_, str>> {
+            Some(super::UNIQUE_VIOLATION.into())
+        }
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+        fn kind(&self) -> sqlx::error::ErrorKind {
+            sqlx::error::ErrorKind::UniqueViolation
+        }
+    }
+
+    #[test]
+    fn maps_database_errors_through_the_orm() {
+        // Each error is wrapped by the ORM, so the mapping has to find the sqlx error underneath.
+        fn status(error: sqlx::Error) -> usize {
+            let error = crate::Error::from(tailwag_orm::Error::from(error));
+            error.into_response().status as usize
+        }
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+
+        assert_eq!(status(sqlx::Error::RowNotFound), 404);
+        assert_eq!(status(sqlx::Error::Database(Box::new(UniqueViolation))), 409);
+        assert_eq!(status(sqlx::Error::Io(refused)), 503);
+        assert_eq!(status(sqlx::Error::Tls("bad certificate".into())), 503);
+        assert_eq!(status(sqlx::Error::PoolTimedOut), 503);
+        assert_eq!(status(sqlx::Error::PoolClosed), 503);
+        assert_eq!(status(sqlx::Error::ColumnNotFound("name".into())), 500);
+    }
+
+    #[test]
+    fn reports_the_path_of_invalid_fields() {
+        #[derive(serde::Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Event {
+            id: u32,
+        }
+        #[derive(serde::Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Calendar {
+            events: Vec<Event>,
+        }
+
+        let mut json =
+            serde_json::Deserializer::from_str(r#"{"events": [{"id": 1}, {"id": "2"}]}"#);
+        match super::deserialize_tracked::<_, Calendar>(&mut json, "Invalid JSON body") {
+            Err(crate::Error::InvalidField {
+                field,
+                ..
+            }) => assert_eq!(field, "events[1].id"),
+            other => panic!("Expected an invalid field, got {:?}", other),
+        }
+
+        let mut json = serde_json::Deserializer::from_str("[]");
+        let result = super::deserialize_tracked::<_, Event>(&mut json, "Invalid JSON body");
+        assert!(
+            matches!(result, Err(crate::Error::BadRequest(msg)) if msg.starts_with("Invalid JSON body"))
+        );
+    }
+}
@@ -3,7 +3,7 @@ use std::{fmt::Display, str::FromStr};
 use crate::{
     application::http::{
         headers::Headers,
-        multipart::MultipartPart,
+        multipart::{MultipartPart, PartContent},
         route::{FromRequest, IntoResponse, PathString, Response},
     },
     Error,
@@ -31,11 +31,11 @@ impl FromRequest for Image {
                 let url = format!("http://localhost:8081/image/{key}"); // TODO: Unhardcode this. Only for localhost right now. Do a find/replace on hardcoded URLs & localhost specifically
                 let title = parts
                     .remove("title")
-                    .map(|title| String::from_utf8(title.content))
+                    .map(|title| title.text_blocking())
                     .unwrap_or(Ok("Untitled Image".into()))?;
                 let description = parts
                     .remove("description")
-                    .map(|description| String::from_utf8(description.content))
+                    .map(|description| description.text_blocking())
                     .unwrap_or(Ok("".into()))?;
 
                 Image {
@@ -47,7 +47,7 @@ impl FromRequest for Image {
                         title,
                         description,
                     },
-                    bytes: file.content,
+                    file: file.content,
                     mime_type,
                 }
             },
@@ -119,19 +119,21 @@ pub async fn save_image(
             return Response::internal_server_error();
         },
     };
-    std::fs::write(filename, image.bytes).unwrap();
+    if let Err(e) = image.file.persist(filename).await {
+        log::error!("Error saving image file: {:?}", e);
+        return Response::internal_server_error();
+    }
     result.into_response()
 }
 pub fn update_image_md() -> impl IntoResponse {
     Response::not_implemented()
 }
 
-#[derive(Clone)]
 pub struct Image {
     pub metadata: ImageMetadata,
     #[allow(unused)]
     pub mime_type: MimeType,
-    pub bytes: Vec<u8>,
+    pub file: PartContent,
 }
 
 #[derive(Clone)]
@@ -1,4 +1,5 @@
-use application::http::route::{IntoResponse, Response};
+use application::http::route::{HttpStatus, IntoResponse, Response};
+use errors::{ProblemDetails, ResponseError};
 
 pub mod application;
 pub mod auth;
@@ -11,12 +12,24 @@ pub mod traits;
 #[derive(Debug)]
 pub enum Error {
     BadRequest(String),
+    /// A single field of the request couldn't be read. `field` is its path, e.g. `events[2].start`.
+    InvalidField {
+        field: String,
+        reason: String,
+    },
     InternalServerError(String),
+    /// The request lacks valid credentials, e.g. a failed login. Ref: RFC 9110 15.5.2
+    Unauthorized,
     TaskSchedculingError(TaskError),
     Conflict,
     NotFound,
     EntityTooLarge,
     UnsupportedMediaType,
+    NotAcceptable,
+    /// The server can't handle the request right now, e.g. because the database is unreachable.
+    ServiceUnavailable(String),
+    /// An error type from outside this crate, which describes its own response.
+    Custom(Box<dyn ResponseError>),
 }
 use tasks::runner::TaskError;
 pub use Error as HttpError;
@@ -30,6 +43,9 @@ impl Error {
     pub fn internal_server_error<T>(msg: &str) -> HttpResult<T> {
         Err(Error::InternalServerError(msg.into()))
     }
+    pub fn unauthorized<T>() -> HttpResult<T> {
+        Err(Error::Unauthorized)
+    }
     pub fn not_found<T>() -> HttpResult<T> {
         Err(Error::NotFound)
     }
@@ -42,11 +58,11 @@ impl Error {
     pub fn unsupported_media_type<T>() -> HttpResult<T> {
         Err(Error::UnsupportedMediaType)
     }
-}
-
-impl<T: ToString> From<T> for Error {
-    fn from(value: T) -> Self {
-        Error::BadRequest(value.to_string())
+    pub fn not_acceptable<T>() -> HttpResult<T> {
+        Err(Error::NotAcceptable)
+    }
+    pub fn service_unavailable<T>(msg: &str) -> HttpResult<T> {
+        Err(Error::ServiceUnavailable(msg.into()))
     }
 }
 
@@ -56,40 +72,87 @@ impl From<TaskError> for Error {
     }
 }
 
-// impl From<tailwag_orm::Error> for Error {
-//     fn from(value: tailwag_orm::Error) -> Self {
-//         todo!()
-//     }
-// }
+impl Error {
+    /// Whether the error's detail can be shown to clients, according to the current service's
+    /// [DetailPolicy](errors::DetailPolicy).
+    pub fn exposes_detail(&self) -> bool {
+        errors::current_detail_policy().exposes(self)
+    }
+}
 
 impl IntoResponse for crate::Error {
     fn into_response(self) -> Response {
-        match self {
+        let exposes_detail = self.exposes_detail();
+        let (status, detail) = match self {
             Error::BadRequest(msg) => {
                 log::warn!("[BAD REQUEST] {}", &msg);
-                Response::bad_request()
+                (HttpStatus::BadRequest, Some(msg))
+            },
+            Error::InvalidField {
+                field,
+                reason,
+            } => {
+                log::warn!("[BAD REQUEST] Invalid field `{}`: {}", &field, &reason);
+                if !exposes_detail {
+                    return ProblemDetails::new(HttpStatus::BadRequest).into_response();
+                }
+                let detail = format!("Invalid value for `{}`: {}", &field, &reason);
+                return ProblemDetails::new(HttpStatus::BadRequest)
+                    .with_detail(detail)
+                    .with_invalid_param(field, reason)
+                    .into_response();
+            },
+            Error::NotFound => (HttpStatus::NotFound, None),
+            Error::Unauthorized => {
+                log::warn!("[UNAUTHORIZED]");
+                // A 401 has to say how to authenticate. Ref: RFC 9110 11.6.1
+                return ProblemDetails::new(HttpStatus::Unauthorized)
+                    .into_response()
+                    .with_header("WWW-Authenticate", "Bearer");
             },
-            Error::NotFound => Response::not_found(),
             Error::InternalServerError(msg) => {
                 log::error!("[INTERNAL SERVER ERROR]: {}", &msg);
-                Response::internal_server_error()
+                (HttpStatus::InternalServerError, Some(msg))
             },
             Error::Conflict => {
                 log::warn!("[CONFLICT]");
-                Response::conflict()
+                (HttpStatus::Conflict, None)
             },
             Error::TaskSchedculingError(task_error) => {
                 log::error!("[TASK SCHEDULING ERROR]: {:?}", task_error);
-                Response::internal_server_error()
+                (HttpStatus::InternalServerError, Some(format!("{:?}", task_error)))
             },
             Error::EntityTooLarge => {
                 log::warn!("[ENTITY TOO LARGE]: ");
-                Response::entity_too_large()
+                (HttpStatus::EntityTooLarge, None)
             },
             Error::UnsupportedMediaType => {
                 log::warn!("[UNSUPPORTED MEDIA TYPE]: ");
-                Response::unsupported_media_type()
+                (HttpStatus::UnsupportedMediaType, None)
+            },
+            Error::NotAcceptable => {
+                log::warn!("[NOT ACCEPTABLE]: ");
+                (HttpStatus::NotAcceptable, None)
+            },
+            Error::ServiceUnavailable(msg) => {
+                log::error!("[SERVICE UNAVAILABLE]: {}", &msg);
+                (HttpStatus::ServiceUnavailable, Some(msg))
+            },
+            Error::Custom(err) => {
+                log::warn!("[{}]: {:?}", err.status(), &err);
+                if exposes_detail {
+                    return err.error_response();
+                }
+                return err.headers().into_iter().fold(
+                    ProblemDetails::new(err.status()).into_response(),
+                    |response, (name, value)| response.with_appended_header(name, value),
+                );
             },
+        };
+        let problem = ProblemDetails::new(status);
+        match detail {
+            Some(detail) if exposes_detail => problem.with_detail(detail).into_response(),
+            _ => problem.into_response(),
         }
     }
 }
@@ -1,8 +1,9 @@
 use std::{
-    sync::{mpsc::Sender, Arc, OnceLock},
+    sync::{Arc, OnceLock},
     thread::sleep,
     time::Duration,
 };
+use tokio::sync::mpsc::UnboundedSender;
 
 use tailwag_web_service::application::{AdminActions, WebService, WebServiceBuildResponse};
 
@@ -58,7 +59,7 @@ pub struct Child {
     value: i64,
 }
 
-type KillSignalCell = OnceLock<Sender<AdminActions>>;
+type KillSignalCell = OnceLock<UnboundedSender<AdminActions>>;
 
 #[tokio::main(flavor = "current_thread")]
 async fn run_service(sender_cell: Arc<KillSignalCell>) {
@@ -68,7 +69,8 @@ async fn run_service(sender_cell: Arc<KillSignalCell>) {
     } = WebService::builder("Hello World works")
         .with_resource::<Parent>()
         .with_resource::<Child>()
-        .build_service();
+        .build_service()
+        .unwrap();
 
     sender_cell.set(sender).unwrap();
     service.run().await.unwrap();
@@ -90,9 +92,7 @@ macro_rules! test_hurl_file {
 
 #[test]
 fn run_hurl_tests() {
-    // I did a quick hack-through to add a signal we can use to kill the server gracefully.
-    // It's a condition on the while loop listening for new requests - will only fire when another request
-    // is received after sending the kill switch.
+    // The admin channel lets us kill the server gracefully once the tests are done.
     let kill_signal_cell = Arc::new(OnceLock::new());
     let ksc = kill_signal_cell.clone();
     let thread = std::thread::Builder::new()
@@ -111,18 +111,5 @@ fn run_hurl_tests() {
     signal.send(AdminActions::KillServer).unwrap();
     println!("Sent kill signal to service");
 
-    // // // The kill signal doesn't fire until another request comes in...
-    // // // definitely a bug but not worth fixing rn, the kill signal was hacked together
-    // // // for these tests anyway, and the replumbing would be a bit of a headache.
-    // // // Smooth killing of service may be needed later though - e.g. I plan to
-    // // // intercept SIGKILL signal so I can cleanly shut down when deploying updates.
-    // hurl::runner::run(
-    //     r#"GET http://localhost:8081/"#,
-    //     &hurl::runner::RunnerOptionsBuilder::new().build(),
-    //     &HashMap::default(),
-    //     &hurl::util::logger::LoggerOptionsBuilder::new().build(),
-    // )
-    // .ok();
-
     thread.join().unwrap();
 }